@@ -1,4 +1,4 @@
-use web_sys::{wasm_bindgen::{JsValue, UnwrapThrowExt}, CanvasRenderingContext2d};
+use web_sys::{wasm_bindgen::UnwrapThrowExt, CanvasRenderingContext2d};
 
 pub struct Context {
     ctx: CanvasRenderingContext2d
@@ -15,11 +15,11 @@ impl Context {
     }
 
     pub fn fill_style(&self, color: &str) {
-        self.ctx.set_fill_style(&JsValue::from_str(color));
+        self.ctx.set_fill_style_str(color);
     }
 
     pub fn stroke_style(&self, color: &str) {
-        self.ctx.set_stroke_style(&JsValue::from_str(color));
+        self.ctx.set_stroke_style_str(color);
     }
 
     pub fn line_width<T: Into<f64>>(&self, width: T) {
@@ -49,6 +49,11 @@ impl Context {
         self.ctx.fill_text(text, x, y).unwrap_throw();
     }
 
+    /// Width of a text in the current font
+    pub fn measure_text(&self, text: &str) -> f64 {
+        self.ctx.measure_text(text).unwrap_throw().width()
+    }
+
     pub fn font(&self, font: &str) {
         self.ctx.set_font(font);
     }
@@ -59,6 +59,57 @@ pub fn draw_disconnect(reason: &String, ctx: &Context) {
     }
 }
 
+pub fn draw_ping(ctx: &Context, ping: u16) {
+    ctx.fill_style("#ffffff");
+    ctx.font("bold 16px sans-serif");
+    ctx.text_align("left");
+    ctx.text_baseline("top");
+    ctx.fill_text(&format!("{} ms", ping), 10.0, 10.0);
+}
+
+/// A chat message above whoever said it, `y` being the top of its entity
+pub fn draw_chat_bubble(ctx: &Context, x: f64, y: f64, text: &str) {
+    ctx.font("bold 18px sans-serif");
+    let width = ctx.measure_text(text) + 16.0;
+    let height = 28.0;
+    let top = y - 12.0 - height;
+
+    ctx.global_alpha(0.5);
+    ctx.fill_style("#000000");
+    ctx.fill_rect(x - width / 2.0, top, width, height);
+    ctx.global_alpha(1.0);
+
+    ctx.fill_style("#ffffff");
+    ctx.text_align("center");
+    ctx.text_baseline("middle");
+    ctx.fill_text(text, x, top + height / 2.0);
+}
+
+pub fn draw_spectating(ctx: &Context, following: Option<u32>) {
+    let text = match following {
+        Some(id) => format!("Spectating #{}, move to look around", id),
+        None => "Spectating, click a tank to follow it".to_string(),
+    };
+    let width: f64 = ctx.canvas_width();
+    let height: f64 = ctx.canvas_height();
+
+    ctx.fill_style("#ffffff");
+    ctx.font("bold 16px sans-serif");
+    ctx.text_align("center");
+    ctx.text_baseline("bottom");
+    ctx.fill_text(&text, width / 2.0, height - 10.0);
+}
+
+pub fn draw_notice(ctx: &Context, text: &str) {
+    let width: f64 = ctx.canvas_width();
+
+    ctx.fill_style("#ffffff");
+    ctx.font("bold 24px sans-serif");
+    ctx.text_align("center");
+    ctx.text_baseline("top");
+    ctx.fill_text(text, width / 2.0, 40.0);
+}
+
 pub fn draw_entity(ctx: &Context, entity: &mut Entity) {
     let game = get_game();
 
@@ -85,7 +136,7 @@ pub fn draw_entity(ctx: &Context, entity: &mut Entity) {
     }
 
     draw_poly(
-        &ctx,
+        ctx,
         mockup.shape,
         entity.pos.x,
         entity.pos.y,
@@ -96,8 +147,17 @@ pub fn draw_entity(ctx: &Context, entity: &mut Entity) {
         &offset_hex(&mockup.color, 30),
         5.0,
     );
+
+    if let Some(name) = &entity.name {
+        ctx.fill_style("#ffffff");
+        ctx.font("bold 18px sans-serif");
+        ctx.text_align("center");
+        ctx.text_baseline("top");
+        ctx.fill_text(name, entity.pos.x, entity.pos.y + mockup.height / 2.0 + 10.0);
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn draw_poly(
     ctx: &Context,
     shape: u8,
@@ -131,14 +191,15 @@ fn draw_poly(
         }
     }
     ctx.close_path();
-    ctx.fill_style(&color);
+    ctx.fill_style(color);
     ctx.fill();
 
     ctx.line_width(stroke_width);
-    ctx.stroke_style(&stroke_color);
+    ctx.stroke_style(stroke_color);
     ctx.stroke();
 }
 
+#[allow(clippy::too_many_arguments)]
 fn draw_trapezoid(
     ctx: &Context,
     x: f64,
@@ -6,17 +6,20 @@ pub struct XY {
 }
 
 pub struct Entity {
-    pub id: u16,
+    #[allow(dead_code)]
+    pub id: u32,
     pub mockup_id: u16,
     pub pos: XY,
     pub size: f64,
     pub angle: f64,
     server_data: (f64, f64, f64, f64),
+    /// Nickname of a player that picked one
+    pub name: Option<String>,
     pub is_player: bool
 }
 
 impl Entity {
-    pub fn new(id: u16, x: f64, y: f64, size: f64, mockup_id: u16, is_player: bool) -> Self {
+    pub fn new(id: u32, x: f64, y: f64, size: f64, mockup_id: u16, name: Option<String>, is_player: bool) -> Self {
         Self {
             id,
             mockup_id,
@@ -24,6 +27,7 @@ impl Entity {
             size,
             angle: 0.0,
             server_data: (x, y, size, 0.0),
+            name,
             is_player
         }
     }
@@ -1,22 +1,46 @@
 use crate::{
     context::Context,
-    draw::{draw_connecting, draw_disconnect, draw_entity, draw_grid},
+    draw::{
+        draw_chat_bubble, draw_connecting, draw_disconnect, draw_entity, draw_grid, draw_notice,
+        draw_ping, draw_spectating
+    },
     entity::Entity,
     listeners::add_event_listeners,
     mockup::Mockups,
-    util::lerp,
-    ProtocolMessage
+    util::lerp
+};
+use diatom_protocol::{
+    quantize::{decode_angle, decode_position, decode_size},
+    ClientPacket, HelloPacket, ServerPacket, Snapshot, SNAPSHOT_HISTORY
 };
 use gloo_console::console_dbg;
 use gloo_utils::window;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use web_sys::{
     js_sys::Uint8Array,
     wasm_bindgen::{closure::Closure, prelude::*},
     BinaryType, CanvasRenderingContext2d, CloseEvent, MessageEvent, WebSocket,
 };
 
-type Entities = HashMap<u16, Entity>;
+type Entities = HashMap<u32, Entity>;
+
+/// Close code of a connection that dropped without a close frame
+const ABNORMAL_CLOSURE: u16 = 1006;
+
+/// Attempts at getting a dropped connection back before giving up
+const MAX_RECONNECTS: u32 = 5;
+
+/// Milliseconds between two reconnect attempts
+const RECONNECT_DELAY: i32 = 1000;
+
+/// Milliseconds a server notice stays on screen
+const NOTICE_DURATION: f64 = 8000.0;
+
+/// Milliseconds a chat message stays above its sender
+const CHAT_DURATION: f64 = 5000.0;
+
+/// Map units a spectator's free camera moves per frame
+const CAMERA_SPEED: f64 = 12.0;
 
 struct Map {
     width: f64,
@@ -36,8 +60,39 @@ impl Map {
     }
 }
 
+/// ## Where a spectator looks
+///
+/// The server sends what's around the same spot, so a free camera tells it where it went.
+struct Camera {
+    /// Entity the camera follows, it's free otherwise
+    follow: Option<u32>,
+    x: f64,
+    y: f64,
+    /// Movement keys held, in the order of [`ClientPacket::Key`]
+    keys: [bool; 4],
+    /// Position of the free camera last sent to the server
+    sent: (f64, f64)
+}
+
+impl Camera {
+    fn new() -> Self {
+        Self {
+            follow: None,
+            x: 0.0,
+            y: 0.0,
+            keys: [false; 4],
+            sent: (f64::NAN, f64::NAN)
+        }
+    }
+}
+
 pub struct Game {
-    pub index: Option<u16>,
+    pub index: Option<u32>,
+    /// Watching the game without a player of our own
+    pub spectating: bool,
+    /// Nickname typed on the start menu, the server cleans it up
+    pub name: String,
+    camera: Camera,
     entities: Entities,
     pub ctx: Context,
     pub colors: HashMap<&'static str, &'static str>,
@@ -45,11 +100,22 @@ pub struct Game {
     map: Map,
     pub mockups: Mockups,
     pub window_scale: f64,
-    pub mouse_angle: f64
-}
-
-fn decode_angle(angle: i16) -> f64 {
-    angle as f64 / i16::MAX as f64 * 360.
+    pub mouse_angle: f64,
+    sent_mouse_angle: f64,
+    snapshots: VecDeque<Snapshot>,
+    socket: Option<WebSocket>,
+    addr: String,
+    /// Resume token of our player, sent back when reconnecting
+    token: Option<String>,
+    reconnects: u32,
+    /// Round-trip time in milliseconds, as measured by the server
+    ping: Option<u16>,
+    /// Last notice from the server's admins, and when to stop showing it
+    notice: Option<(String, f64)>,
+    /// Last chat message of each player, and when to stop showing it
+    chat: HashMap<u32, (String, f64)>,
+    /// The chat box is open, so keys type in it instead of moving
+    pub chatting: bool
 }
 
 impl Game {
@@ -64,6 +130,9 @@ impl Game {
 
         Self {
             index: None,
+            spectating: false,
+            name: String::new(),
+            camera: Camera::new(),
             entities: Entities::new(),
             ctx: Context::new(ctx),
             colors,
@@ -71,67 +140,131 @@ impl Game {
             map: Map::new_empty(),
             mockups: Mockups::new(),
             window_scale: 1.0,
-            mouse_angle: 0.0
+            mouse_angle: 0.0,
+            sent_mouse_angle: 0.0,
+            snapshots: VecDeque::with_capacity(SNAPSHOT_HISTORY),
+            socket: None,
+            addr: String::new(),
+            token: None,
+            reconnects: 0,
+            ping: None,
+            notice: None,
+            chat: HashMap::new(),
+            chatting: false
         }
     }
 
-    pub fn handle_message(&mut self, message: ProtocolMessage) {
-        if let ProtocolMessage::Array(vec) = message {
-            for msg in &vec {
-                if let ProtocolMessage::Array(v) = msg {
-                    match v.as_slice() {
-                        [ProtocolMessage::Float64(w), ProtocolMessage::Float64(h)] =>
-                        // map size
-                        {
-                            self.map.server_width = *w;
-                            self.map.server_height = *h;
+    pub fn handle_message(&mut self, packet: ServerPacket) {
+        match packet {
+            ServerPacket::Welcome(welcome) => {
+                self.index = Some(welcome.id);
+                self.token = Some(welcome.token).filter(|token| !token.is_empty());
+                self.reconnects = 0;
+                self.map.server_width = welcome.map_width;
+                self.map.server_height = welcome.map_height;
+                self.spectating = welcome.spectator;
+                if self.spectating {
+                    match self.camera.follow {
+                        Some(id) => self.send(ClientPacket::Follow(id)),
+                        None => {
+                            self.camera.x = welcome.map_width / 2.0;
+                            self.camera.y = welcome.map_height / 2.0;
+                        }
+                    }
+                }
+                //if let Some((_, entity)) = self.entities.iter_mut().find(|e| e.1.id == id) {
+                //    entity.is_player = true;
+                //}
+            }
+            ServerPacket::Delta(delta) => {
+                let tick = delta.tick;
+                let base = match delta.base {
+                    Some(base) => match self.snapshots.iter().find(|s| s.tick == base) {
+                        Some(snapshot) => Some(snapshot),
+                        None => {
+                            console_dbg!(format!("Missing base snapshot {} for tick {}", base, tick));
+                            return;
                         }
-                        [ProtocolMessage::Uint16(id), ProtocolMessage::Uint16(mockup_id), ProtocolMessage::Int16(angle), ProtocolMessage::Array(bounds)] =>
-                        // entity update
-                        {
-                            if let [ProtocolMessage::Float64(min_x), ProtocolMessage::Float64(min_y), ProtocolMessage::Float64(max_x), ProtocolMessage::Float64(max_y)] =
-                                bounds.as_slice()
-                            {
-                                let x = (min_x + max_x) / 2.0;
-                                let y = (min_y + max_y) / 2.0;
-
-                                self.entities
-                                    .entry(*id)
-                                    .and_modify(|e| {
-                                        e.set_predict(x, y, *max_x - *min_x, decode_angle(*angle));
-                                    })
-                                    .or_insert(Entity::new(*id, x, y, 0.0, *mockup_id, self.index.unwrap_or(u16::MAX) == *id));
+                    },
+                    None => None,
+                };
+                let Some(world) = Snapshot::apply(base, delta) else {
+                    console_dbg!(format!("Failed to apply delta for tick {}", tick));
+                    return;
+                };
+
+                self.map.server_width = world.map_width;
+                self.map.server_height = world.map_height;
+
+                for entity in world.entities.values() {
+                    let x = decode_position(entity.x, world.map_width);
+                    let y = decode_position(entity.y, world.map_height);
+                    let size = decode_size(entity.size);
+                    let angle = decode_angle(entity.angle);
+
+                    self.entities
+                        .entry(entity.id)
+                        .and_modify(|e| {
+                            e.set_predict(x, y, size, angle);
+                            if e.name != entity.name {
+                                e.name.clone_from(&entity.name);
                             }
+                        })
+                        .or_insert_with(|| {
+                            let is_player = self.index.unwrap_or(u32::MAX) == entity.id;
+                            Entity::new(entity.id, x, y, 0.0, entity.mockup_id, entity.name.clone(), is_player)
+                        });
+                }
 
-                            self.entities.retain(|id, _| {
-                                vec.iter().any(|m| match m {
-                                    ProtocolMessage::Array(v) if v.len() == 4 => {
-                                        if let [ProtocolMessage::Uint16(i), _, _, _] = v.as_slice() {
-                                            i == id
-                                        } else {
-                                            false
-                                        }
-                                    }
-                                    _ => false,
-                                })
-                            });
-                        }
-                        _ => {
-                            console_dbg!(v);
-                        }
-                    };
+                self.entities
+                    .retain(|id, _| world.entities.contains_key(id));
+
+                if self.snapshots.len() == SNAPSHOT_HISTORY {
+                    self.snapshots.pop_front();
                 }
+                self.snapshots.push_back(world);
+                self.send(ClientPacket::Ack(tick));
+            }
+            ServerPacket::Ping(ping) => {
+                self.send(ClientPacket::Pong(ping.time));
+                self.ping = ping.rtt.or(self.ping);
+            }
+            ServerPacket::Notice(text) => {
+                self.notice = Some((text, now() + NOTICE_DURATION));
+            }
+            ServerPacket::Chat(chat) => {
+                self.chat.insert(chat.id, (chat.text, now() + CHAT_DURATION));
             }
-        } else if let ProtocolMessage::Uint16(id) = message {
-            self.index = Some(id);
-            //if let Some((_, entity)) = self.entities.iter_mut().find(|e| e.1.id == id) {
-            //    entity.is_player = true;
-            //}
         }
     }
 
-    pub async fn start(&mut self, addr: &str) {
-        let socket = WebSocket::new(addr).unwrap();
+    pub fn send(&self, packet: ClientPacket) {
+        if let Some(socket) = &self.socket {
+            if socket.ready_state() == WebSocket::OPEN {
+                socket.send_with_u8_array(&packet.encode()).unwrap_throw();
+            }
+        }
+    }
+
+    pub async fn start(&mut self, addr: &str, spectate: bool) {
+        self.addr = addr.to_string();
+        self.spectating = spectate;
+        self.connect();
+        add_event_listeners();
+
+        if let Err(e) = self.get_mockups().await {
+            console_dbg!(format!("Failed to get mockups: {:?}", e));
+        };
+
+        console_dbg!(format!(
+            "mockups: {:?}",
+            self.mockups.as_vec()
+        ));
+        self.tick();
+    }
+
+    fn connect(&mut self) {
+        let socket = WebSocket::new(&self.addr).unwrap();
 
         socket.set_binary_type(BinaryType::Arraybuffer);
 
@@ -139,9 +272,28 @@ impl Game {
             Closure::<dyn FnMut(_)>::new(move |event: MessageEvent| {
                 let buf = event.data();
                 let array = Uint8Array::new(&buf);
-                let message = ProtocolMessage::decode(&array.to_vec());
+                match ServerPacket::decode(&array.to_vec()) {
+                    Ok(packet) => get_game().handle_message(packet),
+                    Err(e) => {
+                        console_dbg!(format!("Failed to decode packet: {}", e));
+                    }
+                }
+            })
+            .into_js_value()
+            .as_ref()
+            .unchecked_ref(),
+        ));
 
-                get_game().handle_message(message);
+        socket.set_onopen(Some(
+            Closure::<dyn FnMut()>::new(move || {
+                let game = get_game();
+                let hello = HelloPacket {
+                    resume: game.token.clone(),
+                    spectate: game.spectating,
+                    name: game.name.clone(),
+                    ..HelloPacket::new()
+                };
+                game.send(ClientPacket::Hello(hello));
             })
             .into_js_value()
             .as_ref()
@@ -151,31 +303,125 @@ impl Game {
         socket.set_onclose(Some(
             Closure::<dyn FnMut(_)>::new(move |event: CloseEvent| {
                 unsafe {
-                    if GAME.is_none() {
+                    if (*std::ptr::addr_of!(GAME)).is_none() {
                         return;
                     }
                 }
-                get_game().disconnected = Some(event.reason());
+                let game = get_game();
+                // The connection dropped, our player waits on the server for a while
+                if event.code() == ABNORMAL_CLOSURE
+                    && game.token.is_some()
+                    && game.reconnects < MAX_RECONNECTS
+                {
+                    game.reconnect();
+                    return;
+                }
+                game.disconnected = Some(match event.reason().as_str() {
+                    "" => "Disconnected".to_string(),
+                    reason => reason.to_string(),
+                });
             })
             .into_js_value()
             .as_ref()
             .unchecked_ref(),
         ));
 
-        add_event_listeners(socket);
+        self.socket = Some(socket);
+    }
 
-        if let Err(e) = self.get_mockups().await {
-            console_dbg!(format!("Failed to get mockups: {:?}", e));
-        };
+    /// Connect again after a delay, resuming our player with its token
+    fn reconnect(&mut self) {
+        self.reconnects += 1;
 
-        console_dbg!(format!(
-            "mockups: {:?}",
-            self.mockups.as_vec()
-        ));
-        self.tick();
+        // The new connection starts from a full snapshot
+        self.index = None;
+        self.snapshots.clear();
+        self.sent_mouse_angle = f64::NAN;
+
+        let closure = Closure::once_into_js(|| get_game().connect());
+        window()
+            .set_timeout_with_callback_and_timeout_and_arguments_0(
+                closure.as_ref().unchecked_ref(),
+                RECONNECT_DELAY,
+            )
+            .unwrap_throw();
+    }
+
+    /// Follow an entity with the spectator camera
+    pub fn follow(&mut self, id: u32) {
+        self.camera.follow = Some(id);
+        self.send(ClientPacket::Follow(id));
+    }
+
+    /// Follow the entity under a point of the screen, in CSS pixels
+    pub fn follow_at(&mut self, x: f64, y: f64) {
+        let ratio = window().device_pixel_ratio();
+        let width: f64 = self.ctx.canvas_width();
+        let height: f64 = self.ctx.canvas_height();
+        let x = self.camera.x + (x * ratio - width / 2.0) / self.window_scale;
+        let y = self.camera.y + (y * ratio - height / 2.0) / self.window_scale;
+
+        let picked = self
+            .entities
+            .values()
+            .map(|entity| (entity.id, (entity.pos.x - x).hypot(entity.pos.y - y), entity.mockup_id))
+            .filter(|&(_, distance, mockup_id)| {
+                let mockup = self.mockups.get(mockup_id);
+                distance <= mockup.width.max(mockup.height) / 2.0
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((id, _, _)) = picked {
+            self.follow(id);
+        }
+    }
+
+    /// Press or release a movement key of the spectator camera, which stops following
+    pub fn camera_key(&mut self, key: u8, pressed: bool) {
+        if let Some(held) = self.camera.keys.get_mut(key as usize) {
+            *held = pressed;
+        }
+        if pressed {
+            self.camera.follow = None;
+        }
+    }
+
+    /// Center of the screen on the map, our player or where we spectate from
+    fn view_center(&mut self) -> Option<(f64, f64)> {
+        let index = self.index?;
+        if !self.spectating {
+            let me = self.entities.get(&index)?;
+            return Some((me.pos.x, me.pos.y));
+        }
+
+        let camera = &mut self.camera;
+        if let Some(id) = camera.follow {
+            match self.entities.get(&id) {
+                Some(entity) => (camera.x, camera.y) = (entity.pos.x, entity.pos.y),
+                // It died, the camera stays where it was
+                None => camera.follow = None,
+            }
+        }
+        if camera.follow.is_none() {
+            let axis = |minus: bool, plus: bool| (plus as i8 - minus as i8) as f64 * CAMERA_SPEED;
+            let [up, left, down, right] = camera.keys;
+            camera.x = (camera.x + axis(left, right)).clamp(0.0, self.map.server_width);
+            camera.y = (camera.y + axis(up, down)).clamp(0.0, self.map.server_height);
+        }
+        Some((camera.x, camera.y))
     }
 
     pub fn tick(&mut self) {
+        if self.spectating {
+            let position = (self.camera.x, self.camera.y);
+            if self.camera.follow.is_none() && self.index.is_some() && position != self.camera.sent {
+                self.send(ClientPacket::Camera(position.0, position.1));
+                self.camera.sent = position;
+            }
+        } else if self.mouse_angle != self.sent_mouse_angle {
+            self.send(ClientPacket::Mouse(self.mouse_angle));
+            self.sent_mouse_angle = self.mouse_angle;
+        }
+
         self.update();
 
         let closure = Closure::once_into_js(|| {
@@ -198,18 +444,17 @@ impl Game {
         ctx.fill_style("#c9c9c9");
         ctx.fill_rect(0.0, 0.0, width, height);
 
-        if self.index.is_none() || self.entities.get(&self.index.unwrap_throw()).is_none() {
-            draw_connecting(ctx);
-            return;
-        }
-
         if let Some(reason) = &self.disconnected {
             draw_disconnect(reason, ctx);
 
             return;
         }
 
-        let me = self.entities.get(&self.index.unwrap()).unwrap();
+        let Some((x, y)) = self.view_center() else {
+            draw_connecting(&self.ctx);
+            return;
+        };
+        let ctx = &self.ctx;
 
         ctx.save();
         ctx.line_cap("round");
@@ -220,39 +465,68 @@ impl Game {
 
         ctx.fill_style(self.colors.get("bg").unwrap());
         ctx.fill_rect(
-            width / 2.0 - me.pos.x * self.window_scale,
-            height / 2.0 - me.pos.y * self.window_scale,
+            width / 2.0 - x * self.window_scale,
+            height / 2.0 - y * self.window_scale,
             self.map.width * self.window_scale,
             self.map.height * self.window_scale
         );
 
         draw_grid(
             ctx,
-            width / 2.0 - me.pos.x * self.window_scale,
-            height / 2.0 - me.pos.y * self.window_scale,
+            width / 2.0 - x * self.window_scale,
+            height / 2.0 - y * self.window_scale,
             32.0 * self.window_scale
         );
 
         ctx.translate(width / 2.0, height / 2.0);
         ctx.scale(self.window_scale);
-        ctx.translate(-me.pos.x, -me.pos.y);
+        ctx.translate(-x, -y);
 
         for entity in self.entities.values_mut() {
             draw_entity(ctx, entity);
             entity.predict();
         }
 
+        // Drawn after every entity so no tank covers them
+        let time = now();
+        self.chat.retain(|_, (_, until)| *until > time);
+        for (id, (text, _)) in &self.chat {
+            if let Some(entity) = self.entities.get(id) {
+                let mockup = self.mockups.get(entity.mockup_id);
+                draw_chat_bubble(ctx, entity.pos.x, entity.pos.y - mockup.height / 2.0, text);
+            }
+        }
+
         ctx.restore();
+
+        if let Some(ping) = self.ping {
+            draw_ping(ctx, ping);
+        }
+        if self.spectating {
+            draw_spectating(ctx, self.camera.follow);
+        }
+
+        if self.notice.as_ref().is_some_and(|(_, until)| now() > *until) {
+            self.notice = None;
+        }
+        if let Some((text, _)) = &self.notice {
+            draw_notice(ctx, text);
+        }
     }
 
     async fn get_mockups(&mut self) -> Result<(), reqwest::Error> {
-        let addr = format!("http://localhost:3000/mockups.json");
+        let addr = "http://localhost:3000/mockups.json";
 
         self.mockups.load(serde_json::from_str(reqwest::get(addr).await?.text().await?.trim()).unwrap());
         Ok(())
     }
 }
 
+/// Milliseconds since the page loaded
+fn now() -> f64 {
+    window().performance().unwrap_throw().now()
+}
+
 pub static mut GAME: Option<Box<Game>> = None;
 
 pub fn new_game(ctx: CanvasRenderingContext2d) {
@@ -260,5 +534,5 @@ pub fn new_game(ctx: CanvasRenderingContext2d) {
 }
 
 pub fn get_game() -> &'static mut Game {
-    unsafe { GAME.as_mut().unwrap() }
+    unsafe { (*std::ptr::addr_of_mut!(GAME)).as_mut().unwrap() }
 }
\ No newline at end of file
@@ -2,31 +2,38 @@ mod draw;
 mod entity;
 mod game;
 mod listeners;
-mod protocol;
 mod util;
 mod context;
 mod mockup;
 
 extern crate console_error_panic_hook;
 
+use diatom_protocol::MAX_NAME_LENGTH;
 use game::{get_game, new_game};
 use gloo_utils::{document, window};
-use protocol::Message as ProtocolMessage;
 use std::panic;
 use wasm_bindgen_futures::spawn_local;
 use web_sys::{
     wasm_bindgen::{self, closure::Closure, prelude::*, JsCast},
     CanvasRenderingContext2d, Event, HtmlButtonElement, HtmlCanvasElement, HtmlDivElement,
+    HtmlInputElement,
 };
 
 #[wasm_bindgen(start)]
 fn main() {
     panic::set_hook(Box::new(console_error_panic_hook::hook));
 
-    let addr = "ws://localhost:3000/ws";
-
     let window = window();
 
+    // The page's ?room= picks the room to join, the server's default one otherwise
+    let search = window.location().search().unwrap_or_default();
+    let addr = match query_param(&search, "room") {
+        Some(room) if !room.is_empty() => format!("ws://localhost:3000/ws/{}", room),
+        _ => "ws://localhost:3000/ws".to_string()
+    };
+    // ?follow= is the id of the player a spectator starts following
+    let follow = query_param(&search, "follow").and_then(|id| id.parse::<u32>().ok());
+
     let document = document();
 
     let canvas = document
@@ -51,25 +58,59 @@ fn main() {
         .dyn_into::<HtmlButtonElement>()
         .unwrap_throw();
 
+    let name_input = document
+        .get_element_by_id("name")
+        .unwrap_throw()
+        .dyn_into::<HtmlInputElement>()
+        .unwrap_throw();
+    name_input.set_max_length(MAX_NAME_LENGTH as i32);
+
+    let spectate_button = document
+        .get_element_by_id("spectate")
+        .unwrap_throw()
+        .dyn_into::<HtmlButtonElement>()
+        .unwrap_throw();
+
     new_game(ctx);
 
-    start_button.set_onclick(Some(
-        Closure::<dyn FnMut(_)>::new(move |_: Event| {
-            document
-                .get_element_by_id("startmenu")
-                .unwrap()
-                .dyn_into::<HtmlDivElement>()
-                .unwrap()
-                .style()
-                .set_property("display", "none")
-                .unwrap();
-            canvas.style().set_property("display", "flex").unwrap();
-            spawn_local(async {
-                get_game().start(addr).await;
-            });
-        })
-        .into_js_value()
-        .as_ref()
-        .unchecked_ref(),
-    ));
+    for (button, spectate) in [(start_button, false), (spectate_button, true)] {
+        let document = document.clone();
+        let canvas = canvas.clone();
+        let addr = addr.clone();
+        let name_input = name_input.clone();
+        button.set_onclick(Some(
+            Closure::<dyn FnMut(_)>::new(move |_: Event| {
+                document
+                    .get_element_by_id("startmenu")
+                    .unwrap()
+                    .dyn_into::<HtmlDivElement>()
+                    .unwrap()
+                    .style()
+                    .set_property("display", "none")
+                    .unwrap();
+                canvas.style().set_property("display", "flex").unwrap();
+                let addr = addr.clone();
+                let name = name_input.value().trim().to_string();
+                spawn_local(async move {
+                    let game = get_game();
+                    game.name = name;
+                    if let (true, Some(id)) = (spectate, follow) {
+                        game.follow(id);
+                    }
+                    game.start(&addr, spectate).await;
+                });
+            })
+            .into_js_value()
+            .as_ref()
+            .unchecked_ref(),
+        ));
+    }
+}
+
+/// Value of a parameter of the page's query string
+fn query_param<'a>(search: &'a str, name: &str) -> Option<&'a str> {
+    search
+        .trim_start_matches('?')
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
 }
\ No newline at end of file
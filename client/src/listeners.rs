@@ -1,12 +1,13 @@
-use crate::{game::get_game, ProtocolMessage};
+use crate::game::get_game;
+use diatom_protocol::{ClientPacket, MAX_CHAT_LENGTH, VIEW_HEIGHT, VIEW_WIDTH};
 use gloo_events::{EventListener, EventListenerOptions};
 use gloo_utils::{document, window};
 use web_sys::{
     wasm_bindgen::{prelude::*, JsCast},
-    Event, HtmlCanvasElement, KeyboardEvent, MouseEvent, WebSocket,
+    Event, HtmlCanvasElement, HtmlInputElement, KeyboardEvent, MouseEvent,
 };
 
-pub fn add_event_listeners(socket: WebSocket) {
+pub fn add_event_listeners() {
     let window = window();
     let canvas = document()
         .get_element_by_id("canvas")
@@ -22,8 +23,8 @@ pub fn add_event_listeners(socket: WebSocket) {
         let height = window.inner_height().unwrap().as_f64().unwrap() * ratio;
         canvas.set_width(width as u32);
         canvas.set_height(height as u32);
-        let a = width / 1920.0;
-        let b = height / 1080.0;
+        let a = width / VIEW_WIDTH;
+        let b = height / VIEW_HEIGHT;
         game.window_scale = if a > b { a } else { b };
     };
     resize();
@@ -37,82 +38,62 @@ pub fn add_event_listeners(socket: WebSocket) {
         move |event: &Event| event.prevent_default()
     ).forget();
 
-    let cloned_socket = socket.clone();
-    let cloned_socket_1 = socket.clone();
-    let cloned_socket_2 = socket.clone();
-    let cloned_socket_3 = socket.clone();
+    let chat = chat_input();
+    chat.set_max_length(MAX_CHAT_LENGTH as i32);
+    // Clicking away from the chat box closes it
+    EventListener::new(&chat, "blur", move |_| close_chat()).forget();
 
     EventListener::new(&window, "keydown", move |event: &Event| {
-        if cloned_socket.ready_state() != 1 {
-            return;
-        }
         let event = event.clone().dyn_into::<KeyboardEvent>().unwrap_throw();
-        let key = event.code();
-
-        let num: u8;
-
-        match key.as_str() {
-            "KeyW" | "ArrowUp" => num = 0,
-            "KeyA" | "ArrowLeft" => num = 1,
-            "KeyS" | "ArrowDown" => num = 2,
-            "KeyD" | "ArrowRight" => num = 3,
-            _ => return
-        };
-
-        cloned_socket
-            .send_with_u8_array(
-                &ProtocolMessage::Array(vec![
-                    ProtocolMessage::Uint8(0),
-                    ProtocolMessage::Uint8(num),
-                ])
-                .encode(),
-            )
-            .unwrap_throw();
+        let game = get_game();
+        match event.code().as_str() {
+            // Spectators have nobody to say it
+            "Enter" | "NumpadEnter" if !game.spectating => {
+                toggle_chat();
+                return;
+            }
+            "Escape" if game.chatting => {
+                close_chat();
+                return;
+            }
+            _ if game.chatting => return,
+            _ => {}
+        }
+        if let Some(num) = key_index(&event.code()) {
+            if game.spectating {
+                game.camera_key(num, true);
+            } else {
+                game.send(ClientPacket::Key(num, true));
+            }
+        }
     }).forget();
 
     EventListener::new(&window, "keyup", move |event: &Event| {
-        if socket.ready_state() != 1 {
-            return;
-        }
         let event = event.clone().dyn_into::<KeyboardEvent>().unwrap_throw();
-        let key = event.code();
-
-        let num: u8;
-
-        match key.as_str() {
-            "KeyW" | "ArrowUp" => num = 0,
-            "KeyA" | "ArrowLeft" => num = 1,
-            "KeyS" | "ArrowDown" => num = 2,
-            "KeyD" | "ArrowRight" => num = 3,
-            _ => return
-        };
-
-        socket
-            .send_with_u8_array(
-                &ProtocolMessage::Array(vec![
-                    ProtocolMessage::Uint8(1),
-                    ProtocolMessage::Uint8(num),
-                ])
-                .encode(),
-            )
-            .unwrap_throw();
+        if let Some(num) = key_index(&event.code()) {
+            let game = get_game();
+            if game.spectating {
+                game.camera_key(num, false);
+            } else {
+                game.send(ClientPacket::Key(num, false));
+            }
+        }
     }).forget();
 
-    EventListener::new(&window, "mousedown", move |_: &Event| {
-        if cloned_socket_2.ready_state() != 1 {
-            return;
+    EventListener::new(&window, "mousedown", move |event: &Event| {
+        let game = get_game();
+        if game.spectating {
+            let event = event.clone().dyn_into::<MouseEvent>().unwrap_throw();
+            game.follow_at(event.client_x() as f64, event.client_y() as f64);
+        } else {
+            game.send(ClientPacket::MouseClick(true));
         }
-        cloned_socket_2
-            .send_with_u8_array(&ProtocolMessage::Array(vec![ProtocolMessage::Bool(true)]).encode())
-            .unwrap_throw();
     }).forget();
     EventListener::new(&window, "mouseup", move |_: &Event| {
-        if cloned_socket_3.ready_state() != 1 {
-            return;
+        let game = get_game();
+        if !game.spectating {
+            game.send(ClientPacket::MouseClick(false));
         }
-        cloned_socket_3
-            .send_with_u8_array(&ProtocolMessage::Array(vec![ProtocolMessage::Bool(false)]).encode())
-            .unwrap_throw();
     }).forget();
 
     EventListener::new(&window, "mousemove", move |event: &Event| {
@@ -123,19 +104,67 @@ pub fn add_event_listeners(socket: WebSocket) {
         let x = event.client_x() as f64;
         let y = event.client_y() as f64;
 
-        if cloned_socket_1.ready_state() != 1 {
-            return;
-        }
-
         let window = web_sys::window().unwrap();
         let ratio = window.device_pixel_ratio();
 
         let delta_x = x - width / 2.0 / ratio;
         let delta_y = y - height / 2.0 / ratio;
         let rad = -delta_x.atan2(delta_y);
+        // Sent once per frame by the game, mousemove can fire a lot faster
         game.mouse_angle = rad;
-        cloned_socket_1
-            .send_with_u8_array(&ProtocolMessage::Array(vec![ProtocolMessage::Float64(rad)]).encode())
-            .unwrap();
     }).forget();
-}
\ No newline at end of file
+}
+
+/// The chat box of the page
+fn chat_input() -> HtmlInputElement {
+    document()
+        .get_element_by_id("chat")
+        .unwrap_throw()
+        .dyn_into::<HtmlInputElement>()
+        .unwrap_throw()
+}
+
+/// Open the chat box, or send what's in it and close it
+fn toggle_chat() {
+    let game = get_game();
+    let chat = chat_input();
+    if game.chatting {
+        let text = chat.value();
+        let text = text.trim();
+        if !text.is_empty() {
+            game.send(ClientPacket::Chat(text.to_string()));
+        }
+        close_chat();
+    } else {
+        // The keys held now are released while typing, so stop moving
+        for key in 0..4 {
+            game.send(ClientPacket::Key(key, false));
+        }
+        game.chatting = true;
+        chat.style().set_property("display", "block").unwrap_throw();
+        chat.focus().unwrap_throw();
+    }
+}
+
+fn close_chat() {
+    let game = get_game();
+    if !game.chatting {
+        return;
+    }
+    game.chatting = false;
+    let chat = chat_input();
+    chat.set_value("");
+    chat.style().set_property("display", "none").unwrap_throw();
+    chat.blur().unwrap_throw();
+}
+
+/// Index of a movement key in [`ClientPacket::Key`]
+fn key_index(code: &str) -> Option<u8> {
+    match code {
+        "KeyW" | "ArrowUp" => Some(0),
+        "KeyA" | "ArrowLeft" => Some(1),
+        "KeyS" | "ArrowDown" => Some(2),
+        "KeyD" | "ArrowRight" => Some(3),
+        _ => None
+    }
+}
@@ -0,0 +1,354 @@
+use crate::{Error, Message};
+use serde::{
+    de::{self, value::U32Deserializer, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor},
+    forward_to_deserialize_any,
+};
+use std::vec::IntoIter;
+
+/// Deserialize a `T` from a [`Message`] laid out the way [`to_message`](crate::to_message) writes it
+pub fn from_message<T: DeserializeOwned>(message: Message) -> Result<T, Error> {
+    T::deserialize(Deserializer::new(message))
+}
+
+/// Deserialize a `T` from bytes holding exactly one encoded [`Message`]
+pub fn from_bytes<T: DeserializeOwned>(buf: &[u8]) -> Result<T, Error> {
+    let (message, len) = Message::decode(buf)?;
+    if len != buf.len() {
+        return Err(Error::TrailingBytes(buf.len() - len));
+    }
+    from_message(message)
+}
+
+pub struct Deserializer {
+    message: Message,
+}
+
+impl Deserializer {
+    pub fn new(message: Message) -> Self {
+        Self { message }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.message {
+            Message::Null => visitor.visit_unit(),
+            Message::Bool(b) => visitor.visit_bool(b),
+            Message::Uint8(u) => visitor.visit_u8(u),
+            Message::Int8(i) => visitor.visit_i8(i),
+            Message::Uint16(u) => visitor.visit_u16(u),
+            Message::Int16(i) => visitor.visit_i16(i),
+            Message::Uint32(u) => visitor.visit_u32(u),
+            Message::Int32(i) => visitor.visit_i32(i),
+            Message::Uint64(u) => visitor.visit_u64(u),
+            Message::Int64(i) => visitor.visit_i64(i),
+            Message::Float32(f) => visitor.visit_f32(f),
+            Message::Float64(d) => visitor.visit_f64(d),
+            Message::String(s) => visitor.visit_string(s),
+            Message::Array(a) => visit_array(a, visitor),
+            Message::Object(o) => {
+                let mut map = ObjectAccess {
+                    iter: o.into_iter(),
+                    value: None,
+                };
+                let value = visitor.visit_map(&mut map)?;
+                match map.iter.len() {
+                    0 => Ok(value),
+                    n => Err(de::Error::invalid_length(n, &"fewer entries in object")),
+                }
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.message {
+            Message::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let Message::Array(a) = self.message else {
+            return Err(de::Error::invalid_type(unexpected(&self.message), &"enum array"));
+        };
+        let mut iter = a.into_iter();
+        let index = match iter.next() {
+            Some(Message::Uint8(u)) => u as u32,
+            Some(Message::Uint16(u)) => u as u32,
+            Some(Message::Uint32(u)) => u,
+            Some(other) => {
+                return Err(de::Error::invalid_type(unexpected(&other), &"variant index"))
+            }
+            None => return Err(de::Error::invalid_length(0, &"variant index")),
+        };
+        visitor.visit_enum(EnumAccess { index, iter })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf
+        unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+fn unexpected(message: &Message) -> de::Unexpected<'_> {
+    match message {
+        Message::Null => de::Unexpected::Unit,
+        Message::Bool(b) => de::Unexpected::Bool(*b),
+        Message::Uint8(u) => de::Unexpected::Unsigned(*u as u64),
+        Message::Uint16(u) => de::Unexpected::Unsigned(*u as u64),
+        Message::Uint32(u) => de::Unexpected::Unsigned(*u as u64),
+        Message::Uint64(u) => de::Unexpected::Unsigned(*u),
+        Message::Int8(i) => de::Unexpected::Signed(*i as i64),
+        Message::Int16(i) => de::Unexpected::Signed(*i as i64),
+        Message::Int32(i) => de::Unexpected::Signed(*i as i64),
+        Message::Int64(i) => de::Unexpected::Signed(*i),
+        Message::Float32(f) => de::Unexpected::Float(*f as f64),
+        Message::Float64(d) => de::Unexpected::Float(*d),
+        Message::String(s) => de::Unexpected::Str(s),
+        Message::Array(_) => de::Unexpected::Seq,
+        Message::Object(_) => de::Unexpected::Map,
+    }
+}
+
+fn visit_array<'de, V: Visitor<'de>>(a: Vec<Message>, visitor: V) -> Result<V::Value, Error> {
+    let mut seq = ArrayAccess { iter: a.into_iter() };
+    let value = visitor.visit_seq(&mut seq)?;
+    match seq.iter.len() {
+        0 => Ok(value),
+        n => Err(de::Error::invalid_length(n, &"fewer elements in array")),
+    }
+}
+
+struct ArrayAccess {
+    iter: IntoIter<Message>,
+}
+
+impl<'de> de::SeqAccess<'de> for ArrayAccess {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        match self.iter.next() {
+            Some(message) => seed.deserialize(Deserializer::new(message)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct ObjectAccess {
+    iter: IntoIter<(Message, Message)>,
+    value: Option<Message>,
+}
+
+impl<'de> de::MapAccess<'de> for ObjectAccess {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(Deserializer::new(key)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| <Error as de::Error>::custom("value requested before its key"))?;
+        seed.deserialize(Deserializer::new(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct EnumAccess {
+    index: u32,
+    iter: IntoIter<Message>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = Error;
+    type Variant = VariantAccess;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, VariantAccess), Error> {
+        let index: U32Deserializer<Error> = self.index.into_deserializer();
+        let value = seed.deserialize(index)?;
+        Ok((value, VariantAccess { iter: self.iter }))
+    }
+}
+
+struct VariantAccess {
+    iter: IntoIter<Message>,
+}
+
+impl<'de> de::VariantAccess<'de> for VariantAccess {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        match self.iter.len() {
+            0 => Ok(()),
+            n => Err(de::Error::invalid_length(n, &"unit variant")),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(mut self, seed: T) -> Result<T::Value, Error> {
+        match (self.iter.next(), self.iter.len()) {
+            (Some(message), 0) => seed.deserialize(Deserializer::new(message)),
+            (None, _) => Err(de::Error::invalid_length(0, &"newtype variant")),
+            (Some(_), n) => Err(de::Error::invalid_length(n + 1, &"newtype variant")),
+        }
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        visit_array(self.iter.collect(), visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visit_array(self.iter.collect(), visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{from_bytes, to_bytes, Error};
+    use serde::{de::DeserializeOwned, Deserialize, Serialize};
+    use std::{collections::BTreeMap, fmt::Debug};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Unit;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Newtype(u32);
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Fields {
+        id: u32,
+        name: Option<String>,
+        position: (i16, i16),
+        children: Vec<Fields>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Variants {
+        Unit,
+        Newtype(String),
+        Tuple(u8, bool),
+        Struct { x: f64, y: f64 },
+    }
+
+    fn round_trip<T: Serialize + DeserializeOwned + PartialEq + Debug>(value: T) {
+        let bytes = to_bytes(&value).unwrap();
+        assert_eq!(from_bytes::<T>(&bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn primitives_round_trip() {
+        round_trip(true);
+        round_trip(false);
+        round_trip(u8::MAX);
+        round_trip(i8::MIN);
+        round_trip(u16::MAX);
+        round_trip(i16::MIN);
+        round_trip(u32::MAX);
+        round_trip(i32::MIN);
+        round_trip(u64::MAX);
+        round_trip(i64::MIN);
+        round_trip(1.5f32);
+        round_trip(-0.1f64);
+        round_trip('é');
+        round_trip(String::new());
+        round_trip("diatom ✓".to_string());
+    }
+
+    #[test]
+    fn options_and_units_round_trip() {
+        round_trip(None::<u32>);
+        round_trip(Some(7u32));
+        round_trip(());
+        round_trip(Unit);
+        round_trip(Newtype(42));
+    }
+
+    #[test]
+    fn structs_and_sequences_round_trip() {
+        round_trip(vec![1u16, 2, 3]);
+        round_trip((1u8, "two".to_string(), 3.0f64));
+        round_trip(Fields {
+            id: 1,
+            name: Some("root".to_string()),
+            position: (-5, 9),
+            children: vec![Fields {
+                id: 2,
+                name: None,
+                position: (0, 0),
+                children: Vec::new(),
+            }],
+        });
+    }
+
+    #[test]
+    fn every_kind_of_variant_round_trips() {
+        round_trip(Variants::Unit);
+        round_trip(Variants::Newtype("text".to_string()));
+        round_trip(Variants::Tuple(3, true));
+        round_trip(Variants::Struct { x: 1.0, y: -2.0 });
+        round_trip(vec![Variants::Unit, Variants::Tuple(0, false)]);
+    }
+
+    #[test]
+    fn maps_round_trip() {
+        round_trip(BTreeMap::from([(1u32, "a".to_string()), (2, "b".to_string())]));
+        round_trip(BTreeMap::<String, Vec<u8>>::new());
+    }
+
+    #[test]
+    fn trailing_bytes_are_rejected() {
+        let mut bytes = to_bytes(&5u8).unwrap();
+        bytes.extend([0x00, 0x00]);
+        assert_eq!(from_bytes::<u8>(&bytes), Err(Error::TrailingBytes(2)));
+    }
+
+    #[test]
+    fn wrong_shape_is_an_error() {
+        let bytes = to_bytes(&(1u8, 2u8)).unwrap();
+        assert!(matches!(from_bytes::<Fields>(&bytes), Err(Error::Custom(_))));
+        let bytes = to_bytes(&"text").unwrap();
+        assert!(matches!(from_bytes::<u32>(&bytes), Err(Error::Custom(_))));
+    }
+
+    #[test]
+    fn unknown_variant_is_an_error() {
+        let bytes = to_bytes(&(9u8,)).unwrap();
+        assert!(from_bytes::<Variants>(&bytes).is_err());
+    }
+}
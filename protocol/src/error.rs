@@ -0,0 +1,47 @@
+use crate::DecodeError;
+use serde::{de, ser};
+use std::fmt;
+
+/// Error returned by the serde [`Serializer`](crate::Serializer), [`Deserializer`](crate::Deserializer) and packet decoding
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// A message produced by a `Serialize` or `Deserialize` implementation
+    Custom(String),
+    /// The bytes could not be decoded into a [`Message`](crate::Message)
+    Decode(DecodeError),
+    /// The buffer held more bytes after the first message
+    TrailingBytes(usize),
+    /// The leading opcode of a packet is not known
+    UnknownOpcode(u8),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Custom(msg) => write!(f, "{}", msg),
+            Error::Decode(e) => write!(f, "{}", e),
+            Error::TrailingBytes(n) => write!(f, "{} trailing bytes after message", n),
+            Error::UnknownOpcode(opcode) => write!(f, "unknown packet opcode {:#04x}", opcode),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::Custom(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::Custom(msg.to_string())
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Self {
+        Error::Decode(e)
+    }
+}
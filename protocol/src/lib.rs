@@ -0,0 +1,14 @@
+mod de;
+mod error;
+mod message;
+mod packets;
+pub mod quantize;
+mod ser;
+mod snapshot;
+
+pub use de::{from_bytes, from_message, Deserializer};
+pub use error::Error;
+pub use message::{DecodeError, Message};
+pub use packets::*;
+pub use ser::{to_bytes, to_message, Serializer};
+pub use snapshot::*;
@@ -0,0 +1,425 @@
+// MADE BY: https://github.com/GDenisC
+
+use std::fmt;
+
+#[allow(dead_code)]
+pub enum Message {
+    Null,
+    Bool(bool),
+    Uint8(u8),
+    Int8(i8),
+    Uint16(u16),
+    Int16(i16),
+    Uint32(u32),
+    Int32(i32),
+    Uint64(u64),
+    Int64(i64),
+    Float32(f32),
+    Float64(f64),
+    String(String),
+    Array(Vec<Message>),
+    Object(Vec<(Message, Message)>),
+}
+
+impl fmt::Debug for Message {
+    fn fmt(&self, x: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Message::Null => write!(x, "Null"),
+            Message::Bool(b) => write!(x, "Bool({})", b),
+            Message::Uint8(u) => write!(x, "Uint8({})", u),
+            Message::Int8(i) => write!(x, "Int8({})", i),
+            Message::Uint16(u) => write!(x, "Uint16({})", u),
+            Message::Int16(i) => write!(x, "Int16({})", i),
+            Message::Uint32(u) => write!(x, "Uint32({})", u),
+            Message::Int32(i) => write!(x, "Int32({})", i),
+            Message::Uint64(u) => write!(x, "Uint64({})", u),
+            Message::Int64(i) => write!(x, "Int64({})", i),
+            Message::Float32(f) => write!(x, "Float32({})", f),
+            Message::Float64(d) => write!(x, "Float64({})", d),
+            Message::String(s) => write!(x, "String(\"{}\")", s),
+            Message::Array(a) => write!(x, "Array({:?})", a),
+            Message::Object(o) => write!(x, "Object({:?})", o),
+        }
+    }
+}
+
+/// Something an encoded message can be written into
+trait Output {
+    fn put(&mut self, bytes: &[u8]);
+}
+
+impl Output for Vec<u8> {
+    fn put(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+}
+
+/// Counts the bytes written instead of storing them, used by [`Message::length`]
+struct Counter(usize);
+
+impl Output for Counter {
+    fn put(&mut self, bytes: &[u8]) {
+        self.0 += bytes.len();
+    }
+}
+
+/// ## Write a length as an unsigned LEB128 varint
+/// ### 7 bits per byte, least significant group first, high bit set on every byte but the last
+///
+/// Example 1: 127 -> {0x7f}
+///
+/// Example 2: 300 -> {0xac, 0x02}
+fn write_varint(mut i: usize, out: &mut impl Output) {
+    loop {
+        let byte = (i & 0x7f) as u8;
+        i >>= 7;
+        if i == 0 {
+            out.put(&[byte]);
+            return;
+        }
+        out.put(&[byte | 0x80]);
+    }
+}
+
+/// Read an unsigned LEB128 varint, returning the value and the number of bytes it took up
+fn read_varint(b: &[u8]) -> Result<(usize, usize), DecodeError> {
+    let mut value: usize = 0;
+    for (i, byte) in b.iter().enumerate() {
+        let shift = i * 7;
+        let bits = (byte & 0x7f) as usize;
+        if shift >= usize::BITS as usize || (bits << shift) >> shift != bits {
+            return Err(DecodeError::InvalidLength);
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err(DecodeError::UnexpectedEnd)
+}
+
+fn take(buf: &[u8], offset: usize, len: usize) -> Result<&[u8], DecodeError> {
+    buf.get(offset..offset.checked_add(len).ok_or(DecodeError::UnexpectedEnd)?)
+        .ok_or(DecodeError::UnexpectedEnd)
+}
+
+fn take_array<const N: usize>(buf: &[u8]) -> Result<[u8; N], DecodeError> {
+    Ok(take(buf, 1, N)?.try_into().unwrap())
+}
+
+/// Arrays and objects nested deeper than this are rejected instead of recursing further
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// The buffer ended before the message did
+    UnexpectedEnd,
+    /// The type tag is not a known message type
+    UnknownTag(u8),
+    /// The varint length prefix does not fit in a `usize`
+    InvalidLength,
+    /// A string is not valid UTF-8
+    InvalidUtf8,
+    /// Arrays or objects are nested deeper than [`MAX_DEPTH`]
+    TooDeep,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of message"),
+            DecodeError::UnknownTag(tag) => write!(f, "unknown message type {:#04x}", tag),
+            DecodeError::InvalidLength => write!(f, "length prefix is too large"),
+            DecodeError::InvalidUtf8 => write!(f, "string is not valid UTF-8"),
+            DecodeError::TooDeep => write!(f, "message is nested too deeply"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut v = Vec::new();
+        self.write(&mut v);
+        v
+    }
+
+    fn write(&self, v: &mut impl Output) {
+        match self {
+            Message::Null => v.put(&[0x00]),
+            Message::Bool(b) => v.put(&[if *b { 0x01 } else { 0x02 }]),
+            Message::Uint8(u) => v.put(&[0x03, *u]),
+            Message::Int8(i) => v.put(&[0x04, *i as u8]),
+            Message::Uint16(u) => {
+                v.put(&[0x05]);
+                v.put(&u.to_le_bytes());
+            }
+            Message::Int16(i) => {
+                v.put(&[0x06]);
+                v.put(&i.to_le_bytes());
+            }
+            Message::Uint32(u) => {
+                v.put(&[0x07]);
+                v.put(&u.to_le_bytes());
+            }
+            Message::Int32(i) => {
+                v.put(&[0x08]);
+                v.put(&i.to_le_bytes());
+            }
+            Message::Uint64(u) => {
+                v.put(&[0x09]);
+                v.put(&u.to_le_bytes());
+            }
+            Message::Int64(i) => {
+                v.put(&[0x0a]);
+                v.put(&i.to_le_bytes());
+            }
+            Message::Float32(f) => {
+                v.put(&[0x0b]);
+                v.put(&f.to_le_bytes());
+            }
+            Message::Float64(d) => {
+                v.put(&[0x0c]);
+                v.put(&d.to_le_bytes());
+            }
+            Message::String(s) => {
+                v.put(&[0x0d]);
+                write_varint(s.len(), v);
+                v.put(s.as_bytes());
+            }
+            Message::Array(a) => {
+                v.put(&[0x0e]);
+                write_varint(a.len(), v);
+                for m in a {
+                    m.write(v);
+                }
+            }
+            Message::Object(o) => {
+                v.put(&[0x0f]);
+                write_varint(o.len(), v);
+                for (k, m) in o {
+                    k.write(v);
+                    m.write(v);
+                }
+            }
+        }
+    }
+
+    /// Decode a single message from the start of `buf`.
+    ///
+    /// Returns the message together with the number of bytes it took up.
+    pub fn decode(buf: &[u8]) -> Result<(Self, usize), DecodeError> {
+        Message::decode_nested(buf, 0)
+    }
+
+    fn decode_nested(buf: &[u8], depth: usize) -> Result<(Self, usize), DecodeError> {
+        let message_type = *buf.first().ok_or(DecodeError::UnexpectedEnd)?;
+        Ok(match message_type {
+            0x00 => (Message::Null, 1),
+            0x01 => (Message::Bool(true), 1),
+            0x02 => (Message::Bool(false), 1),
+            0x03 => (Message::Uint8(take_array::<1>(buf)?[0]), 2),
+            0x04 => (Message::Int8(take_array::<1>(buf)?[0] as i8), 2),
+            0x05 => (Message::Uint16(u16::from_le_bytes(take_array(buf)?)), 3),
+            0x06 => (Message::Int16(i16::from_le_bytes(take_array(buf)?)), 3),
+            0x07 => (Message::Uint32(u32::from_le_bytes(take_array(buf)?)), 5),
+            0x08 => (Message::Int32(i32::from_le_bytes(take_array(buf)?)), 5),
+            0x09 => (Message::Uint64(u64::from_le_bytes(take_array(buf)?)), 9),
+            0x0a => (Message::Int64(i64::from_le_bytes(take_array(buf)?)), 9),
+            0x0b => (Message::Float32(f32::from_le_bytes(take_array(buf)?)), 5),
+            0x0c => (Message::Float64(f64::from_le_bytes(take_array(buf)?)), 9),
+            0x0d => {
+                let (length, offset) = read_varint(&buf[1..])?;
+                let bytes = take(buf, 1 + offset, length)?;
+                let s = std::str::from_utf8(bytes).map_err(|_| DecodeError::InvalidUtf8)?;
+                (Message::String(s.to_string()), 1 + offset + length)
+            }
+            0x0e => {
+                if depth >= MAX_DEPTH {
+                    return Err(DecodeError::TooDeep);
+                }
+                let (length, offset) = read_varint(&buf[1..])?;
+                let mut a = Vec::new();
+                let mut index = 1 + offset;
+                for _ in 0..length {
+                    let (message, len) = Message::decode_nested(&buf[index..], depth + 1)?;
+                    index += len;
+                    a.push(message);
+                }
+                (Message::Array(a), index)
+            }
+            0x0f => {
+                if depth >= MAX_DEPTH {
+                    return Err(DecodeError::TooDeep);
+                }
+                let (length, offset) = read_varint(&buf[1..])?;
+                let mut o = Vec::new();
+                let mut index = 1 + offset;
+                for _ in 0..length {
+                    let (key, len) = Message::decode_nested(&buf[index..], depth + 1)?;
+                    index += len;
+                    let (value, len) = Message::decode_nested(&buf[index..], depth + 1)?;
+                    index += len;
+                    o.push((key, value));
+                }
+                (Message::Object(o), index)
+            }
+            tag => return Err(DecodeError::UnknownTag(tag)),
+        })
+    }
+
+    /// The number of bytes [`Message::encode`] produces for this message
+    pub fn length(&self) -> usize {
+        let mut counter = Counter(0);
+        self.write(&mut counter);
+        counter.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `depth` arrays, each one holding the next, the innermost one empty
+    fn nested_arrays(depth: usize) -> Vec<u8> {
+        let mut buf = [0x0e, 0x01].repeat(depth - 1);
+        buf.extend([0x0e, 0x00]);
+        buf
+    }
+
+    /// Bytes [`write_varint`] takes for `i`
+    fn varint(i: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_varint(i, &mut out);
+        out
+    }
+
+    #[test]
+    fn varint_examples() {
+        assert_eq!(varint(0), [0x00]);
+        assert_eq!(varint(127), [0x7f]);
+        assert_eq!(varint(128), [0x80, 0x01]);
+        assert_eq!(varint(300), [0xac, 0x02]);
+        assert_eq!(varint(65536), [0x80, 0x80, 0x04]);
+    }
+
+    #[test]
+    fn varint_round_trips_around_every_byte_boundary() {
+        for i in [0, 127, 128, 255, 256, 16383, 16384, 65535, 65536, usize::MAX] {
+            let bytes = varint(i);
+            assert_eq!(read_varint(&bytes), Ok((i, bytes.len())), "{i}");
+        }
+    }
+
+    #[test]
+    fn string_and_array_lengths_round_trip() {
+        for (length, prefix) in [
+            (0, 1),
+            (127, 1),
+            (128, 2),
+            (255, 2),
+            (256, 2),
+            (16383, 2),
+            (16384, 3),
+            (65535, 3),
+            (65536, 3),
+        ] {
+            let string = Message::String("a".repeat(length));
+            let buf = string.encode();
+            assert_eq!(buf.len(), 1 + prefix + length, "{length}");
+            assert_eq!(string.length(), buf.len());
+            match Message::decode(&buf) {
+                Ok((Message::String(s), read)) => {
+                    assert_eq!(s.len(), length);
+                    assert_eq!(read, buf.len());
+                }
+                other => panic!("{length}: {other:?}"),
+            }
+
+            let array = Message::Array((0..length).map(|_| Message::Null).collect());
+            let buf = array.encode();
+            assert_eq!(buf.len(), 1 + prefix + length, "{length}");
+            match Message::decode(&buf) {
+                Ok((Message::Array(a), read)) => {
+                    assert_eq!(a.len(), length);
+                    assert_eq!(read, buf.len());
+                }
+                other => panic!("{length}: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_varint_is_rejected() {
+        assert_eq!(read_varint(&[0x80]), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(read_varint(&[0xff, 0xff]), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(Message::decode(&[0x0d, 0x80]).unwrap_err(), DecodeError::UnexpectedEnd);
+    }
+
+    #[test]
+    fn overlong_varint_is_rejected() {
+        // Eleven groups of 7 bits is past any usize
+        let mut bytes = vec![0x80; 10];
+        bytes.push(0x01);
+        assert_eq!(read_varint(&bytes), Err(DecodeError::InvalidLength));
+        // Ten groups, but the last one has bits past the 64th
+        let mut bytes = vec![0xff; 9];
+        bytes.push(0x7f);
+        assert_eq!(read_varint(&bytes), Err(DecodeError::InvalidLength));
+    }
+
+    #[test]
+    fn empty_buffer_is_an_unexpected_end() {
+        assert_eq!(Message::decode(&[]).unwrap_err(), DecodeError::UnexpectedEnd);
+    }
+
+    #[test]
+    fn cut_off_number_is_an_unexpected_end() {
+        assert_eq!(Message::decode(&[0x05, 0x01]).unwrap_err(), DecodeError::UnexpectedEnd);
+        assert_eq!(Message::decode(&[0x0c, 0, 0, 0]).unwrap_err(), DecodeError::UnexpectedEnd);
+    }
+
+    #[test]
+    fn string_shorter_than_its_length_is_an_unexpected_end() {
+        assert_eq!(Message::decode(&[0x0d, 0x03, b'a', b'b']).unwrap_err(), DecodeError::UnexpectedEnd);
+    }
+
+    #[test]
+    fn array_missing_elements_is_an_unexpected_end() {
+        assert_eq!(Message::decode(&[0x0e, 0x02, 0x00]).unwrap_err(), DecodeError::UnexpectedEnd);
+    }
+
+    #[test]
+    fn unknown_tag_is_rejected() {
+        assert_eq!(Message::decode(&[0x10]).unwrap_err(), DecodeError::UnknownTag(0x10));
+        assert_eq!(Message::decode(&[0xff, 0x00]).unwrap_err(), DecodeError::UnknownTag(0xff));
+    }
+
+    #[test]
+    fn length_too_large_for_usize_is_rejected() {
+        let mut buf = vec![0x0d];
+        buf.extend([0xff; 10]);
+        buf.push(0x01);
+        assert_eq!(Message::decode(&buf).unwrap_err(), DecodeError::InvalidLength);
+    }
+
+    #[test]
+    fn invalid_utf8_is_rejected() {
+        assert_eq!(Message::decode(&[0x0d, 0x02, 0xff, 0xfe]).unwrap_err(), DecodeError::InvalidUtf8);
+    }
+
+    #[test]
+    fn nesting_stops_at_max_depth() {
+        let buf = nested_arrays(MAX_DEPTH);
+        assert_eq!(Message::decode(&buf).unwrap().1, buf.len());
+        assert_eq!(Message::decode(&nested_arrays(MAX_DEPTH + 1)).unwrap_err(), DecodeError::TooDeep);
+    }
+
+    #[test]
+    fn nested_objects_count_toward_the_depth() {
+        let mut buf = [0x0f, 0x01, 0x00].repeat(MAX_DEPTH);
+        buf.extend([0x0f, 0x00]);
+        assert_eq!(Message::decode(&buf).unwrap_err(), DecodeError::TooDeep);
+    }
+}
@@ -0,0 +1,376 @@
+use crate::{from_bytes, to_bytes, DecodeError, DeltaPacket, Error};
+use serde::{Deserialize, Serialize};
+
+/// ## A single entity inside of a [`Snapshot`](crate::Snapshot)
+/// ### [id, mockup id, x, y, size, angle, name or null]
+///
+/// Position, size and angle are fixed point, see [`crate::quantize`]. Only players have a
+/// name, and it never changes, so it's only sent along with the rest of a new entity.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntityPacket {
+    pub id: u32,
+    pub mockup_id: u16,
+    pub x: i16,
+    pub y: i16,
+    pub size: u16,
+    pub angle: u16,
+    pub name: Option<String>,
+}
+
+/// Bumped whenever the client and the server stop understanding each other
+pub const PROTOCOL_VERSION: u16 = 9;
+
+/// Short git hash of the commit this crate was built from
+pub const BUILD_HASH: &str = env!("DIATOM_BUILD_HASH");
+
+/// Longest nickname, in characters
+pub const MAX_NAME_LENGTH: usize = 16;
+
+/// ## First packet sent by the client
+/// ### [protocol version, build hash, resume token or null, spectate, nickname]
+///
+/// A resume token from an earlier [`WelcomePacket`] takes back that player if it's still around,
+/// with the nickname it had. Spectators get snapshots without having a player, and ignore the
+/// resume token and the nickname.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HelloPacket {
+    pub version: u16,
+    pub build: String,
+    pub resume: Option<String>,
+    pub spectate: bool,
+    /// Empty for a player without a nickname
+    pub name: String,
+}
+
+impl HelloPacket {
+    pub fn new() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            build: BUILD_HASH.to_string(),
+            resume: None,
+            spectate: false,
+            name: String::new(),
+        }
+    }
+}
+
+impl Default for HelloPacket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// ## The server's answer to a [`HelloPacket`]
+/// ### [protocol version, id of the player's entity, ticks per second, map width, map height, resume token, spectator]
+///
+/// Spectators get an id that no entity has, and an empty resume token. A client can be made a
+/// spectator without asking to be, like by a server playing a replay.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WelcomePacket {
+    pub version: u16,
+    pub id: u32,
+    pub tick_rate: f64,
+    pub map_width: f64,
+    pub map_height: f64,
+    pub token: String,
+    pub spectator: bool,
+}
+
+/// ## Sent to every client on an interval, to be echoed back in a [`ClientPacket::Pong`]
+/// ### [server time in milliseconds, smoothed round-trip time in milliseconds or null]
+///
+/// The round-trip time is the client's own, and null until its first pong.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PingPacket {
+    pub time: u32,
+    pub rtt: Option<u16>,
+}
+
+/// Longest chat message, in characters
+pub const MAX_CHAT_LENGTH: usize = 100;
+
+/// ## A chat message, relayed to every client of the room
+/// ### [id of the sender's entity, text]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatPacket {
+    pub id: u32,
+    pub text: String,
+}
+
+/// Opcode followed by the encoded payload
+fn frame<T: Serialize + ?Sized>(opcode: u8, payload: &T) -> Vec<u8> {
+    let mut v = vec![opcode];
+    v.extend(to_bytes(payload).expect("packets always serialize"));
+    v
+}
+
+fn split_opcode(buf: &[u8]) -> Result<(u8, &[u8]), Error> {
+    match buf.split_first() {
+        Some((opcode, payload)) => Ok((*opcode, payload)),
+        None => Err(Error::Decode(DecodeError::UnexpectedEnd)),
+    }
+}
+
+/// ## Packets sent by the client
+/// ### {opcode}, {payload}
+///
+/// - 0x00 Key: [key, pressed]
+/// - 0x01 Mouse: angle
+/// - 0x02 MouseClick: pressed
+/// - 0x03 Ack: tick of the last snapshot applied
+/// - 0x04 Hello: [`HelloPacket`]
+/// - 0x05 Pong: time of the [`PingPacket`] it answers
+/// - 0x06 Follow: id of the entity a spectator's camera follows
+/// - 0x07 Camera: [x, y] of a spectator's free camera, which stops following
+/// - 0x08 Chat: text said by the player, at most [`MAX_CHAT_LENGTH`] characters
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientPacket {
+    Key(u8, bool),
+    Mouse(f64),
+    MouseClick(bool),
+    Ack(u32),
+    Hello(HelloPacket),
+    Pong(u32),
+    Follow(u32),
+    Camera(f64, f64),
+    Chat(String),
+}
+
+impl ClientPacket {
+    pub fn opcode(&self) -> u8 {
+        match self {
+            ClientPacket::Key(..) => 0x00,
+            ClientPacket::Mouse(_) => 0x01,
+            ClientPacket::MouseClick(_) => 0x02,
+            ClientPacket::Ack(_) => 0x03,
+            ClientPacket::Hello(_) => 0x04,
+            ClientPacket::Pong(_) => 0x05,
+            ClientPacket::Follow(_) => 0x06,
+            ClientPacket::Camera(..) => 0x07,
+            ClientPacket::Chat(_) => 0x08,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            ClientPacket::Key(key, pressed) => frame(self.opcode(), &(key, pressed)),
+            ClientPacket::Mouse(rad) => frame(self.opcode(), rad),
+            ClientPacket::MouseClick(pressed) => frame(self.opcode(), pressed),
+            ClientPacket::Ack(tick) => frame(self.opcode(), tick),
+            ClientPacket::Hello(hello) => frame(self.opcode(), hello),
+            ClientPacket::Pong(time) => frame(self.opcode(), time),
+            ClientPacket::Follow(id) => frame(self.opcode(), id),
+            ClientPacket::Camera(x, y) => frame(self.opcode(), &(x, y)),
+            ClientPacket::Chat(text) => frame(self.opcode(), text),
+        }
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, Error> {
+        let (opcode, payload) = split_opcode(buf)?;
+        Ok(match opcode {
+            0x00 => {
+                let (key, pressed) = from_bytes(payload)?;
+                ClientPacket::Key(key, pressed)
+            }
+            0x01 => ClientPacket::Mouse(from_bytes(payload)?),
+            0x02 => ClientPacket::MouseClick(from_bytes(payload)?),
+            0x03 => ClientPacket::Ack(from_bytes(payload)?),
+            0x04 => ClientPacket::Hello(from_bytes(payload)?),
+            0x05 => ClientPacket::Pong(from_bytes(payload)?),
+            0x06 => ClientPacket::Follow(from_bytes(payload)?),
+            0x07 => {
+                let (x, y) = from_bytes(payload)?;
+                ClientPacket::Camera(x, y)
+            }
+            0x08 => ClientPacket::Chat(from_bytes(payload)?),
+            _ => return Err(Error::UnknownOpcode(opcode)),
+        })
+    }
+}
+
+/// ## Packets sent by the server
+/// ### {opcode}, {payload}
+///
+/// - 0x00 Welcome: [`WelcomePacket`]
+/// - 0x01 Delta: [`DeltaPacket`]
+/// - 0x02 Ping: [`PingPacket`]
+/// - 0x03 Notice: text shown to every player, sent by the server's admins
+/// - 0x04 Chat: [`ChatPacket`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerPacket {
+    Welcome(WelcomePacket),
+    Delta(DeltaPacket),
+    Ping(PingPacket),
+    Notice(String),
+    Chat(ChatPacket),
+}
+
+impl ServerPacket {
+    pub fn opcode(&self) -> u8 {
+        match self {
+            ServerPacket::Welcome(_) => 0x00,
+            ServerPacket::Delta(_) => 0x01,
+            ServerPacket::Ping(_) => 0x02,
+            ServerPacket::Notice(_) => 0x03,
+            ServerPacket::Chat(_) => 0x04,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            ServerPacket::Welcome(welcome) => frame(self.opcode(), welcome),
+            ServerPacket::Delta(delta) => frame(self.opcode(), delta),
+            ServerPacket::Ping(ping) => frame(self.opcode(), ping),
+            ServerPacket::Notice(text) => frame(self.opcode(), text),
+            ServerPacket::Chat(chat) => frame(self.opcode(), chat),
+        }
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, Error> {
+        let (opcode, payload) = split_opcode(buf)?;
+        Ok(match opcode {
+            0x00 => ServerPacket::Welcome(from_bytes(payload)?),
+            0x01 => ServerPacket::Delta(from_bytes(payload)?),
+            0x02 => ServerPacket::Ping(from_bytes(payload)?),
+            0x03 => ServerPacket::Notice(from_bytes(payload)?),
+            0x04 => ServerPacket::Chat(from_bytes(payload)?),
+            _ => return Err(Error::UnknownOpcode(opcode)),
+        })
+    }
+}
+
+/// ## A gun of a [`Mockup`], served as JSON from `/mockups.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GunMockup {
+    pub offset: f64,
+    pub direction: f64,
+    pub width: f64,
+    pub height: f64,
+    pub angle: f64,
+    pub aspect: f64,
+    pub color: String,
+    pub shape: u8,
+}
+
+/// ## How an entity looks, served as JSON from `/mockups.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mockup {
+    pub index: u16,
+    pub color: String,
+    pub label: String,
+    pub shape: u8,
+    pub width: f64,
+    pub height: f64,
+    pub health: u8,
+    pub guns: Vec<GunMockup>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EntityDelta;
+
+    fn client_round_trip(packet: ClientPacket, opcode: u8) {
+        let bytes = packet.encode();
+        assert_eq!(bytes[0], opcode, "{packet:?}");
+        assert_eq!(ClientPacket::decode(&bytes), Ok(packet));
+    }
+
+    fn server_round_trip(packet: ServerPacket, opcode: u8) {
+        let bytes = packet.encode();
+        assert_eq!(bytes[0], opcode, "{packet:?}");
+        assert_eq!(ServerPacket::decode(&bytes), Ok(packet));
+    }
+
+    #[test]
+    fn every_client_packet_round_trips() {
+        client_round_trip(ClientPacket::Key(2, true), 0x00);
+        client_round_trip(ClientPacket::Mouse(-1.25), 0x01);
+        client_round_trip(ClientPacket::MouseClick(false), 0x02);
+        client_round_trip(ClientPacket::Ack(u32::MAX), 0x03);
+        client_round_trip(
+            ClientPacket::Hello(HelloPacket {
+                resume: Some("token".to_string()),
+                spectate: true,
+                name: "tank".to_string(),
+                ..HelloPacket::new()
+            }),
+            0x04,
+        );
+        client_round_trip(ClientPacket::Hello(HelloPacket::new()), 0x04);
+        client_round_trip(ClientPacket::Pong(123_456), 0x05);
+        client_round_trip(ClientPacket::Follow(7), 0x06);
+        client_round_trip(ClientPacket::Camera(640.0, 12.5), 0x07);
+        client_round_trip(ClientPacket::Chat("hello ✓".to_string()), 0x08);
+    }
+
+    #[test]
+    fn every_server_packet_round_trips() {
+        server_round_trip(
+            ServerPacket::Welcome(WelcomePacket {
+                version: PROTOCOL_VERSION,
+                id: 1,
+                tick_rate: 60.0,
+                map_width: 1280.0,
+                map_height: 720.0,
+                token: "token".to_string(),
+                spectator: false,
+            }),
+            0x00,
+        );
+        server_round_trip(
+            ServerPacket::Delta(DeltaPacket {
+                tick: 10,
+                base: Some(8),
+                map: None,
+                created: vec![EntityPacket {
+                    id: 3,
+                    mockup_id: 0,
+                    x: -100,
+                    y: 100,
+                    size: 1040,
+                    angle: 16384,
+                    name: Some("tank".to_string()),
+                }],
+                updated: vec![EntityDelta {
+                    id: 4,
+                    x: Some(5),
+                    angle: Some(0),
+                    ..EntityDelta::default()
+                }],
+                removed: vec![5, 6],
+            }),
+            0x01,
+        );
+        server_round_trip(ServerPacket::Ping(PingPacket { time: 99, rtt: None }), 0x02);
+        server_round_trip(ServerPacket::Ping(PingPacket { time: 99, rtt: Some(40) }), 0x02);
+        server_round_trip(ServerPacket::Notice("restarting soon".to_string()), 0x03);
+        server_round_trip(
+            ServerPacket::Chat(ChatPacket {
+                id: 3,
+                text: "gg".to_string(),
+            }),
+            0x04,
+        );
+    }
+
+    #[test]
+    fn unknown_opcodes_are_rejected() {
+        assert_eq!(ClientPacket::decode(&[0x09, 0x00]), Err(Error::UnknownOpcode(0x09)));
+        assert_eq!(ClientPacket::decode(&[0xff]), Err(Error::UnknownOpcode(0xff)));
+        assert_eq!(ServerPacket::decode(&[0x05, 0x00]), Err(Error::UnknownOpcode(0x05)));
+        assert_eq!(ServerPacket::decode(&[0xff]), Err(Error::UnknownOpcode(0xff)));
+    }
+
+    #[test]
+    fn empty_and_malformed_packets_are_errors() {
+        assert_eq!(ClientPacket::decode(&[]), Err(Error::Decode(DecodeError::UnexpectedEnd)));
+        assert_eq!(ServerPacket::decode(&[]), Err(Error::Decode(DecodeError::UnexpectedEnd)));
+        // An ack without its tick
+        assert_eq!(ClientPacket::decode(&[0x03]), Err(Error::Decode(DecodeError::UnexpectedEnd)));
+        // A key packet holding a string
+        let mut bytes = vec![0x00];
+        bytes.extend(to_bytes("key").unwrap());
+        assert!(ClientPacket::decode(&bytes).is_err());
+    }
+}
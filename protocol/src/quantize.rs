@@ -0,0 +1,115 @@
+use std::f64::consts::TAU;
+
+/// Steps a map edge is divided into. The rest of the `i16` range covers entities outside the map
+pub const POSITION_STEPS: f64 = 16384.0;
+
+/// Steps per unit of size
+pub const SIZE_STEPS: f64 = 16.0;
+
+/// Steps in a full turn
+pub const ANGLE_STEPS: f64 = 65536.0;
+
+/// ## Convert a coordinate to fixed point relative to the map
+/// ### 0 -> 0, extent -> [`POSITION_STEPS`]
+pub fn encode_position(position: f64, extent: f64) -> i16 {
+    (position / extent * POSITION_STEPS)
+        .round()
+        .clamp(i16::MIN as f64, i16::MAX as f64) as i16
+}
+
+pub fn decode_position(position: i16, extent: f64) -> f64 {
+    position as f64 / POSITION_STEPS * extent
+}
+
+/// ## Convert a size to fixed point
+/// ### 1/16 of a unit, up to 4096 units
+pub fn encode_size(size: f64) -> u16 {
+    (size * SIZE_STEPS).round().clamp(0.0, u16::MAX as f64) as u16
+}
+
+pub fn decode_size(size: u16) -> f64 {
+    size as f64 / SIZE_STEPS
+}
+
+/// ## Convert an angle in radians to a fraction of a turn
+/// ### 0 -> 0, π -> 32768, 2π wraps back to 0
+pub fn encode_angle(angle: f64) -> u16 {
+    ((angle.rem_euclid(TAU) / TAU * ANGLE_STEPS).round() as u32 % ANGLE_STEPS as u32) as u16
+}
+
+pub fn decode_angle(angle: u16) -> f64 {
+    angle as f64 / ANGLE_STEPS * TAU
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    /// Distance between two angles, going the short way around
+    fn angle_diff(a: f64, b: f64) -> f64 {
+        let d = (a - b).rem_euclid(TAU);
+        d.min(TAU - d)
+    }
+
+    #[test]
+    fn angle_round_trips_every_step() {
+        for q in 0..=u16::MAX {
+            assert_eq!(encode_angle(decode_angle(q)), q);
+        }
+    }
+
+    #[test]
+    fn angle_error_is_within_half_a_step() {
+        let max_error = TAU / ANGLE_STEPS / 2.0;
+        for i in -10_000..10_000 {
+            let angle = i as f64 * 0.001_3;
+            let error = angle_diff(decode_angle(encode_angle(angle)), angle);
+            assert!(error <= max_error + 1e-12, "{angle}: {error}");
+        }
+    }
+
+    #[test]
+    fn angle_wraps() {
+        assert_eq!(encode_angle(0.0), 0);
+        assert_eq!(encode_angle(TAU), 0);
+        assert_eq!(encode_angle(PI), 32768);
+        assert_eq!(encode_angle(-PI / 2.0), 49152);
+        assert_eq!(encode_angle(TAU - 1e-9), 0);
+    }
+
+    #[test]
+    fn position_round_trips_every_step() {
+        for extent in [1280.0, 1000.0, 4096.0, 333.3] {
+            for q in i16::MIN..=i16::MAX {
+                assert_eq!(encode_position(decode_position(q, extent), extent), q);
+            }
+        }
+    }
+
+    #[test]
+    fn position_error_is_within_half_a_step() {
+        let extent = 1280.0;
+        let max_error = extent / POSITION_STEPS / 2.0;
+        for i in 0..10_000 {
+            let position = i as f64 * 0.128_7;
+            let error = (decode_position(encode_position(position, extent), extent) - position).abs();
+            assert!(error <= max_error + 1e-9, "{position}: {error}");
+        }
+    }
+
+    #[test]
+    fn position_clamps_far_outside_the_map() {
+        assert_eq!(encode_position(1e9, 1280.0), i16::MAX);
+        assert_eq!(encode_position(-1e9, 1280.0), i16::MIN);
+    }
+
+    #[test]
+    fn size_round_trips() {
+        for q in 0..=u16::MAX {
+            assert_eq!(encode_size(decode_size(q)), q);
+        }
+        assert_eq!(decode_size(encode_size(65.0)), 65.0);
+        assert_eq!(encode_size(-1.0), 0);
+    }
+}
@@ -0,0 +1,311 @@
+use crate::{Error, Message};
+use serde::{ser, Serialize};
+
+/// Serialize any `T: Serialize` into a [`Message`].
+///
+/// - structs, tuples and sequences become `Array`s, with struct fields in declaration order
+/// - maps become `Object`s
+/// - `None`, `()` and unit structs become `Null`
+/// - enum variants become an `Array` led by the variant index, followed by its fields
+pub fn to_message<T: Serialize + ?Sized>(value: &T) -> Result<Message, Error> {
+    value.serialize(Serializer)
+}
+
+/// Serialize any `T: Serialize` straight into bytes
+pub fn to_bytes<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+    Ok(to_message(value)?.encode())
+}
+
+fn variant_index(index: u32) -> Message {
+    match u8::try_from(index) {
+        Ok(index) => Message::Uint8(index),
+        Err(_) => Message::Uint32(index),
+    }
+}
+
+pub struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = Message;
+    type Error = Error;
+
+    type SerializeSeq = SerializeArray;
+    type SerializeTuple = SerializeArray;
+    type SerializeTupleStruct = SerializeArray;
+    type SerializeTupleVariant = SerializeArray;
+    type SerializeMap = SerializeObject;
+    type SerializeStruct = SerializeArray;
+    type SerializeStructVariant = SerializeArray;
+
+    fn serialize_bool(self, v: bool) -> Result<Message, Error> {
+        Ok(Message::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Message, Error> {
+        Ok(Message::Int8(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Message, Error> {
+        Ok(Message::Int16(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Message, Error> {
+        Ok(Message::Int32(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Message, Error> {
+        Ok(Message::Int64(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Message, Error> {
+        Ok(Message::Uint8(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Message, Error> {
+        Ok(Message::Uint16(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Message, Error> {
+        Ok(Message::Uint32(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Message, Error> {
+        Ok(Message::Uint64(v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Message, Error> {
+        Ok(Message::Float32(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Message, Error> {
+        Ok(Message::Float64(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Message, Error> {
+        Ok(Message::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Message, Error> {
+        Ok(Message::String(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Message, Error> {
+        Ok(Message::Array(v.iter().map(|b| Message::Uint8(*b)).collect()))
+    }
+
+    fn serialize_none(self) -> Result<Message, Error> {
+        Ok(Message::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Message, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Message, Error> {
+        Ok(Message::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Message, Error> {
+        Ok(Message::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<Message, Error> {
+        Ok(Message::Array(vec![self::variant_index(variant_index)]))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Message, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<Message, Error> {
+        Ok(Message::Array(vec![
+            self::variant_index(variant_index),
+            value.serialize(self)?,
+        ]))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeArray, Error> {
+        Ok(SerializeArray {
+            vec: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeArray, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeArray, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeArray, Error> {
+        let mut vec = Vec::with_capacity(len + 1);
+        vec.push(self::variant_index(variant_index));
+        Ok(SerializeArray { vec })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<SerializeObject, Error> {
+        Ok(SerializeObject {
+            pairs: Vec::with_capacity(len.unwrap_or(0)),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeArray, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeArray, Error> {
+        self.serialize_tuple_variant(name, variant_index, variant, len)
+    }
+}
+
+pub struct SerializeArray {
+    vec: Vec<Message>,
+}
+
+impl ser::SerializeSeq for SerializeArray {
+    type Ok = Message;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.vec.push(value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Message, Error> {
+        Ok(Message::Array(self.vec))
+    }
+}
+
+impl ser::SerializeTuple for SerializeArray {
+    type Ok = Message;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Message, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeArray {
+    type Ok = Message;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Message, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeArray {
+    type Ok = Message;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Message, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeStruct for SerializeArray {
+    type Ok = Message;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Message, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeArray {
+    type Ok = Message;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Message, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+pub struct SerializeObject {
+    pairs: Vec<(Message, Message)>,
+    key: Option<Message>,
+}
+
+impl ser::SerializeMap for SerializeObject {
+    type Ok = Message;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = Some(key.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| <Error as ser::Error>::custom("value serialized before its key"))?;
+        self.pairs.push((key, value.serialize(Serializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Message, Error> {
+        Ok(Message::Object(self.pairs))
+    }
+}
@@ -0,0 +1,468 @@
+use crate::EntityPacket;
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    ser::SerializeSeq,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{collections::BTreeMap, fmt};
+
+/// How many snapshots the server and the client keep around to diff against
+pub const SNAPSHOT_HISTORY: usize = 64;
+
+/// Width of the world a client draws around its player at most, whatever the window size
+pub const VIEW_WIDTH: f64 = 1920.0;
+
+/// Height of the world a client draws around its player at most, whatever the window size
+pub const VIEW_HEIGHT: f64 = 1080.0;
+
+/// ## The whole world at a given tick
+///
+/// Never sent as is, see [`Snapshot::diff`] and [`Snapshot::apply`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub tick: u32,
+    pub entities: BTreeMap<u32, EntityPacket>,
+    pub map_width: f64,
+    pub map_height: f64,
+}
+
+/// ## The changes between two snapshots
+/// ### [tick, base tick or null, [map width, map height] or null, [created, ...], [updated, ...], [removed id, ...]]
+///
+/// A `base` of null means the delta is against an empty world, so it holds every entity.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeltaPacket {
+    pub tick: u32,
+    pub base: Option<u32>,
+    pub map: Option<(f64, f64)>,
+    pub created: Vec<EntityPacket>,
+    pub updated: Vec<EntityDelta>,
+    pub removed: Vec<u32>,
+}
+
+const MOCKUP_ID: u8 = 1 << 0;
+const X: u8 = 1 << 1;
+const Y: u8 = 1 << 2;
+const SIZE: u8 = 1 << 3;
+const ANGLE: u8 = 1 << 4;
+
+/// ## The changed fields of an entity present in both snapshots
+/// ### [id, mask, changed field, ...]
+///
+/// The mask has one bit per field: mockup id, x, y, size, angle.
+/// Only the fields whose bit is set follow it, in that order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EntityDelta {
+    pub id: u32,
+    pub mockup_id: Option<u16>,
+    pub x: Option<i16>,
+    pub y: Option<i16>,
+    pub size: Option<u16>,
+    pub angle: Option<u16>,
+}
+
+/// `Some(new)` if it differs from `old`
+fn changed<T: PartialEq + Copy>(old: T, new: T) -> Option<T> {
+    (old != new).then_some(new)
+}
+
+impl EntityDelta {
+    fn between(base: &EntityPacket, entity: &EntityPacket) -> Option<Self> {
+        let delta = EntityDelta {
+            id: entity.id,
+            mockup_id: changed(base.mockup_id, entity.mockup_id),
+            x: changed(base.x, entity.x),
+            y: changed(base.y, entity.y),
+            size: changed(base.size, entity.size),
+            angle: changed(base.angle, entity.angle),
+        };
+
+        (delta.mask() != 0).then_some(delta)
+    }
+
+    fn mask(&self) -> u8 {
+        let mut mask = 0;
+        for (present, bit) in [
+            (self.mockup_id.is_some(), MOCKUP_ID),
+            (self.x.is_some(), X),
+            (self.y.is_some(), Y),
+            (self.size.is_some(), SIZE),
+            (self.angle.is_some(), ANGLE),
+        ] {
+            if present {
+                mask |= bit;
+            }
+        }
+        mask
+    }
+
+    fn apply_to(&self, entity: &mut EntityPacket) {
+        entity.mockup_id = self.mockup_id.unwrap_or(entity.mockup_id);
+        entity.x = self.x.unwrap_or(entity.x);
+        entity.y = self.y.unwrap_or(entity.y);
+        entity.size = self.size.unwrap_or(entity.size);
+        entity.angle = self.angle.unwrap_or(entity.angle);
+    }
+}
+
+impl Serialize for EntityDelta {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mask = self.mask();
+        let mut seq = serializer.serialize_seq(Some(2 + mask.count_ones() as usize))?;
+        seq.serialize_element(&self.id)?;
+        seq.serialize_element(&mask)?;
+        if let Some(mockup_id) = self.mockup_id {
+            seq.serialize_element(&mockup_id)?;
+        }
+        if let Some(x) = self.x {
+            seq.serialize_element(&x)?;
+        }
+        if let Some(y) = self.y {
+            seq.serialize_element(&y)?;
+        }
+        if let Some(size) = self.size {
+            seq.serialize_element(&size)?;
+        }
+        if let Some(angle) = self.angle {
+            seq.serialize_element(&angle)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for EntityDelta {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntityDeltaVisitor;
+
+        impl<'de> Visitor<'de> for EntityDeltaVisitor {
+            type Value = EntityDelta;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an entity delta array")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<EntityDelta, A::Error> {
+                let mut index = 0;
+                let id = next(&mut seq, &mut index)?;
+                let mask: u8 = next(&mut seq, &mut index)?;
+                Ok(EntityDelta {
+                    id,
+                    mockup_id: field(&mut seq, &mut index, mask & MOCKUP_ID != 0)?,
+                    x: field(&mut seq, &mut index, mask & X != 0)?,
+                    y: field(&mut seq, &mut index, mask & Y != 0)?,
+                    size: field(&mut seq, &mut index, mask & SIZE != 0)?,
+                    angle: field(&mut seq, &mut index, mask & ANGLE != 0)?,
+                })
+            }
+        }
+
+        fn next<'de, T: Deserialize<'de>, A: SeqAccess<'de>>(
+            seq: &mut A,
+            index: &mut usize,
+        ) -> Result<T, A::Error> {
+            *index += 1;
+            seq.next_element()?
+                .ok_or_else(|| de::Error::invalid_length(*index - 1, &"a field for every mask bit"))
+        }
+
+        fn field<'de, T: Deserialize<'de>, A: SeqAccess<'de>>(
+            seq: &mut A,
+            index: &mut usize,
+            present: bool,
+        ) -> Result<Option<T>, A::Error> {
+            if present {
+                next(seq, index).map(Some)
+            } else {
+                Ok(None)
+            }
+        }
+
+        deserializer.deserialize_seq(EntityDeltaVisitor)
+    }
+}
+
+impl Snapshot {
+    /// Everything that changed since `base`, or the whole snapshot if there is no base
+    pub fn diff(&self, base: Option<&Snapshot>) -> DeltaPacket {
+        let Some(base) = base else {
+            return DeltaPacket {
+                tick: self.tick,
+                base: None,
+                map: Some((self.map_width, self.map_height)),
+                created: self.entities.values().cloned().collect(),
+                updated: Vec::new(),
+                removed: Vec::new(),
+            };
+        };
+
+        let mut created = Vec::new();
+        let mut updated = Vec::new();
+        for (id, entity) in &self.entities {
+            match base.entities.get(id) {
+                Some(old) if old.name == entity.name => {
+                    updated.extend(EntityDelta::between(old, entity))
+                }
+                // Names aren't in deltas, a different one means another entity took the id
+                _ => created.push(entity.clone()),
+            }
+        }
+
+        let removed = base
+            .entities
+            .keys()
+            .filter(|id| !self.entities.contains_key(id))
+            .copied()
+            .collect();
+
+        let map = (self.map_width != base.map_width || self.map_height != base.map_height)
+            .then_some((self.map_width, self.map_height));
+
+        DeltaPacket {
+            tick: self.tick,
+            base: Some(base.tick),
+            map,
+            created,
+            updated,
+            removed,
+        }
+    }
+
+    /// Rebuild the snapshot a delta was made from.
+    ///
+    /// Returns `None` if `base` isn't the snapshot the delta was made against.
+    pub fn apply(base: Option<&Snapshot>, delta: DeltaPacket) -> Option<Snapshot> {
+        if base.map(|b| b.tick) != delta.base {
+            return None;
+        }
+
+        let (mut entities, base_map) = match base {
+            Some(base) => (base.entities.clone(), Some((base.map_width, base.map_height))),
+            None => (BTreeMap::new(), None),
+        };
+        let (map_width, map_height) = delta.map.or(base_map)?;
+
+        for id in &delta.removed {
+            entities.remove(id);
+        }
+        for entity in delta.created {
+            entities.insert(entity.id, entity);
+        }
+        for change in &delta.updated {
+            change.apply_to(entities.get_mut(&change.id)?);
+        }
+
+        Some(Snapshot {
+            tick: delta.tick,
+            entities,
+            map_width,
+            map_height,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{from_bytes, to_bytes, to_message, Message};
+
+    fn entity(id: u32, x: i16, y: i16) -> EntityPacket {
+        EntityPacket {
+            id,
+            mockup_id: 0,
+            x,
+            y,
+            size: 1040,
+            angle: 0,
+            name: None,
+        }
+    }
+
+    fn snapshot(tick: u32, entities: Vec<EntityPacket>) -> Snapshot {
+        Snapshot {
+            tick,
+            entities: entities.into_iter().map(|e| (e.id, e)).collect(),
+            map_width: 1280.0,
+            map_height: 1280.0,
+        }
+    }
+
+    /// Diff `current` against `base` and apply it back, checking it's the same snapshot
+    fn round_trip(base: &Snapshot, current: &Snapshot) -> DeltaPacket {
+        let delta = current.diff(Some(base));
+        assert_eq!(Snapshot::apply(Some(base), delta.clone()).as_ref(), Some(current));
+        delta
+    }
+
+    #[test]
+    fn no_base_means_every_entity() {
+        let current = snapshot(5, vec![entity(1, 0, 0), entity(2, 10, 10)]);
+        let delta = current.diff(None);
+        assert_eq!(delta.base, None);
+        assert_eq!(delta.map, Some((1280.0, 1280.0)));
+        assert_eq!(delta.created.len(), 2);
+        assert!(delta.updated.is_empty() && delta.removed.is_empty());
+        assert_eq!(Snapshot::apply(None, delta), Some(current));
+    }
+
+    #[test]
+    fn unchanged_snapshot_gives_an_empty_delta() {
+        let base = snapshot(1, vec![entity(1, 0, 0)]);
+        let current = Snapshot { tick: 2, ..base.clone() };
+        let delta = round_trip(&base, &current);
+        assert_eq!(delta.base, Some(1));
+        assert_eq!(delta.map, None);
+        assert!(delta.created.is_empty() && delta.updated.is_empty() && delta.removed.is_empty());
+    }
+
+    #[test]
+    fn added_entities_are_created() {
+        let base = snapshot(1, vec![entity(1, 0, 0)]);
+        let current = snapshot(2, vec![entity(1, 0, 0), entity(2, 5, 5)]);
+        let delta = round_trip(&base, &current);
+        assert_eq!(delta.created, vec![entity(2, 5, 5)]);
+        assert!(delta.updated.is_empty() && delta.removed.is_empty());
+    }
+
+    #[test]
+    fn missing_entities_are_removed() {
+        let base = snapshot(1, vec![entity(1, 0, 0), entity(2, 5, 5), entity(3, 9, 9)]);
+        let current = snapshot(2, vec![entity(2, 5, 5)]);
+        let delta = round_trip(&base, &current);
+        assert_eq!(delta.removed, vec![1, 3]);
+        assert!(delta.created.is_empty() && delta.updated.is_empty());
+    }
+
+    #[test]
+    fn only_changed_fields_are_sent() {
+        let base = snapshot(1, vec![entity(1, 0, 0), entity(2, 0, 0)]);
+        let mut moved = entity(1, 0, 7);
+        moved.angle = 100;
+        let mut grown = entity(2, 0, 0);
+        grown.mockup_id = 3;
+        grown.size = 2000;
+        let current = snapshot(2, vec![moved, grown]);
+
+        let delta = round_trip(&base, &current);
+        assert_eq!(
+            delta.updated,
+            vec![
+                EntityDelta {
+                    id: 1,
+                    y: Some(7),
+                    angle: Some(100),
+                    ..EntityDelta::default()
+                },
+                EntityDelta {
+                    id: 2,
+                    mockup_id: Some(3),
+                    size: Some(2000),
+                    ..EntityDelta::default()
+                },
+            ]
+        );
+        assert_eq!(delta.updated[0].mask(), Y | ANGLE);
+        assert_eq!(delta.updated[1].mask(), MOCKUP_ID | SIZE);
+    }
+
+    #[test]
+    fn renamed_entity_is_created_again() {
+        let mut named = entity(1, 0, 0);
+        named.name = Some("old".to_string());
+        let base = snapshot(1, vec![named.clone()]);
+        named.name = Some("new".to_string());
+        let current = snapshot(2, vec![named.clone()]);
+
+        let delta = round_trip(&base, &current);
+        assert_eq!(delta.created, vec![named]);
+        assert!(delta.updated.is_empty() && delta.removed.is_empty());
+    }
+
+    #[test]
+    fn map_size_change_is_sent() {
+        let base = snapshot(1, vec![entity(1, 0, 0)]);
+        let current = Snapshot {
+            tick: 2,
+            map_width: 2000.0,
+            ..base.clone()
+        };
+        let delta = round_trip(&base, &current);
+        assert_eq!(delta.map, Some((2000.0, 1280.0)));
+    }
+
+    #[test]
+    fn mixed_changes_round_trip() {
+        let base = snapshot(1, vec![entity(1, 0, 0), entity(2, 5, 5), entity(3, 9, 9)]);
+        let current = snapshot(2, vec![entity(1, 1, 0), entity(3, 9, 9), entity(4, -3, 3)]);
+        round_trip(&base, &current);
+        round_trip(&current, &base);
+    }
+
+    #[test]
+    fn wrong_base_is_refused() {
+        let base = snapshot(1, vec![entity(1, 0, 0)]);
+        let other = snapshot(2, vec![entity(1, 0, 0)]);
+        let current = snapshot(3, vec![entity(1, 4, 0)]);
+        let delta = current.diff(Some(&base));
+        assert_eq!(Snapshot::apply(Some(&other), delta.clone()), None);
+        assert_eq!(Snapshot::apply(None, delta), None);
+        assert_eq!(Snapshot::apply(Some(&base), current.diff(None)), None);
+    }
+
+    #[test]
+    fn update_of_a_missing_entity_is_refused() {
+        let base = snapshot(1, vec![entity(1, 0, 0)]);
+        let delta = DeltaPacket {
+            tick: 2,
+            base: Some(1),
+            map: None,
+            created: Vec::new(),
+            updated: vec![EntityDelta {
+                id: 9,
+                x: Some(1),
+                ..EntityDelta::default()
+            }],
+            removed: Vec::new(),
+        };
+        assert_eq!(Snapshot::apply(Some(&base), delta), None);
+    }
+
+    #[test]
+    fn entity_delta_serializes_only_the_masked_fields() {
+        for mask in 1..32u8 {
+            let delta = EntityDelta {
+                id: 7,
+                mockup_id: (mask & MOCKUP_ID != 0).then_some(1),
+                x: (mask & X != 0).then_some(-2),
+                y: (mask & Y != 0).then_some(3),
+                size: (mask & SIZE != 0).then_some(4),
+                angle: (mask & ANGLE != 0).then_some(5),
+            };
+            assert_eq!(delta.mask(), mask);
+            let message = to_message(&delta).unwrap();
+            match &message {
+                Message::Array(fields) => {
+                    assert_eq!(fields.len(), 2 + mask.count_ones() as usize)
+                }
+                other => panic!("{other:?}"),
+            }
+            let bytes = to_bytes(&delta).unwrap();
+            assert_eq!(from_bytes::<EntityDelta>(&bytes), Ok(delta));
+        }
+    }
+
+    #[test]
+    fn entity_delta_missing_a_masked_field_is_an_error() {
+        // Mask says x and y, only x follows
+        let bytes = to_bytes(&(7u32, X | Y, 5i16)).unwrap();
+        assert!(from_bytes::<EntityDelta>(&bytes).is_err());
+    }
+
+    #[test]
+    fn delta_packet_round_trips() {
+        let base = snapshot(1, vec![entity(1, 0, 0), entity(2, 5, 5)]);
+        let current = snapshot(2, vec![entity(1, 1, 0), entity(3, 9, 9)]);
+        let delta = current.diff(Some(&base));
+        let bytes = to_bytes(&delta).unwrap();
+        assert_eq!(from_bytes::<DeltaPacket>(&bytes), Ok(delta));
+    }
+}
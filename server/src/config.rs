@@ -0,0 +1,552 @@
+use clap::Parser;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fmt, fs, io,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing_subscriber::EnvFilter;
+
+/// Config file read when none is given, the defaults are used if it doesn't exist
+const DEFAULT_CONFIG: &str = "diatom.toml";
+
+/// Most players a room can be configured for, ids are u32 so this is about what one game
+/// thread can keep up with
+const PLAYER_LIMIT: usize = 10_000;
+
+/// ## Everything the server can be configured with
+///
+/// Loaded from a TOML file, then overridden by environment variables, then by CLI flags.
+/// Every field is optional in the file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address to listen on
+    pub bind: IpAddr,
+    pub port: u16,
+    /// Game updates per second
+    pub tick_rate: f64,
+    /// Snapshots sent to clients per second, at most the tick rate
+    pub snapshot_rate: f64,
+    /// Map size of the rooms that don't set their own
+    pub map_width: f64,
+    pub map_height: f64,
+    /// Players in a room at once, the next ones are turned away
+    pub max_players: usize,
+    /// Spectators in a room at once, on top of the players, 0 to turn spectating off
+    pub max_spectators: usize,
+    /// Words masked out of chat messages, whole words only and ignoring case
+    pub chat_filter: Vec<String>,
+    /// Seconds a disconnected player is kept for its client to come back, 0 to remove it at once
+    pub resume_grace: f64,
+    /// TOML file of tank definitions, the built-in ones are used if unset
+    pub definitions: Option<PathBuf>,
+    /// Rooms open from the start, the first one is joined by clients that don't pick one
+    pub rooms: Vec<RoomConfig>,
+    /// Rooms open at once, clients can open more of them until there are this many
+    pub max_rooms: usize,
+    /// Seconds a room opened by a client stays open once everyone left
+    pub empty_room_timeout: f64,
+    /// Bearer token of the `/admin` endpoints, they're disabled if unset
+    pub admin_token: Option<String>,
+    /// WebSocket connections open at once from one address
+    pub max_connections_per_ip: usize,
+    /// Origins of the pages allowed to connect, like `https://diatom.io`, any if empty
+    pub allowed_origins: Vec<String>,
+    /// TOML file the ban list is kept in, bans are forgotten on restart if unset
+    pub bans_file: Option<PathBuf>,
+    /// Folder each room is recorded to, one replay file per room opened. Nothing is recorded if unset
+    pub record_dir: Option<PathBuf>,
+    /// Replay file played to every client instead of running any room
+    pub replay: Option<PathBuf>,
+    /// How many times faster than it was recorded the replay is played
+    pub replay_speed: f64,
+    /// Lowest level logged, or per module directives like `info,diatom_server::network=debug`
+    pub log_level: String,
+    pub log_format: LogFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One line per event
+    #[default]
+    Text,
+    /// Several lines per event, easier to read in a terminal
+    Pretty,
+    /// One JSON object per line, for log collectors
+    Json,
+}
+
+/// ## A room open from the start
+///
+/// Unset fields are taken from the [`ServerConfig`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoomConfig {
+    pub name: String,
+    #[serde(default)]
+    pub mode: GameMode,
+    pub map_width: Option<f64>,
+    pub map_height: Option<f64>,
+    pub max_players: Option<usize>,
+}
+
+impl RoomConfig {
+    /// A room with every setting taken from the server's
+    pub fn named(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            mode: GameMode::default(),
+            map_width: None,
+            map_height: None,
+            max_players: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GameMode {
+    /// Every player for themselves, the only mode so far
+    #[default]
+    Ffa,
+}
+
+/// Room names are used in URLs, so they're kept short and plain
+pub fn valid_room_name(name: &str) -> bool {
+    (1..=32).contains(&name.len())
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 3000,
+            tick_rate: 60.0,
+            snapshot_rate: 30.0,
+            map_width: 40.0 * 32.0,
+            map_height: 40.0 * 32.0,
+            max_players: 50,
+            max_spectators: 20,
+            chat_filter: Vec::new(),
+            resume_grace: 30.0,
+            definitions: None,
+            rooms: vec![RoomConfig::named("main")],
+            max_rooms: 16,
+            empty_room_timeout: 60.0,
+            admin_token: None,
+            max_connections_per_ip: 8,
+            allowed_origins: Vec::new(),
+            bans_file: None,
+            record_dir: None,
+            replay: None,
+            replay_speed: 1.0,
+            log_level: "info".to_string(),
+            log_format: LogFormat::default(),
+        }
+    }
+}
+
+#[derive(Parser)]
+#[command(about = "The diatom.io game server")]
+struct Args {
+    /// Config file [default: diatom.toml if it exists]
+    #[arg(short, long, env = "DIATOM_CONFIG")]
+    config: Option<PathBuf>,
+    /// Address to listen on
+    #[arg(long, env = "DIATOM_BIND")]
+    bind: Option<IpAddr>,
+    #[arg(short, long, env = "DIATOM_PORT")]
+    port: Option<u16>,
+    /// Game updates per second
+    #[arg(long, env = "DIATOM_TICK_RATE")]
+    tick_rate: Option<f64>,
+    /// Snapshots sent to clients per second
+    #[arg(long, env = "DIATOM_SNAPSHOT_RATE")]
+    snapshot_rate: Option<f64>,
+    #[arg(long, env = "DIATOM_MAP_WIDTH")]
+    map_width: Option<f64>,
+    #[arg(long, env = "DIATOM_MAP_HEIGHT")]
+    map_height: Option<f64>,
+    #[arg(long, env = "DIATOM_MAX_PLAYERS")]
+    max_players: Option<usize>,
+    /// Spectators in a room at once, on top of the players
+    #[arg(long, env = "DIATOM_MAX_SPECTATORS")]
+    max_spectators: Option<usize>,
+    /// Words masked out of chat messages, separated by commas
+    #[arg(long, env = "DIATOM_CHAT_FILTER", value_delimiter = ',')]
+    chat_filter: Option<Vec<String>>,
+    /// Seconds a disconnected player is kept for its client to come back
+    #[arg(long, env = "DIATOM_RESUME_GRACE")]
+    resume_grace: Option<f64>,
+    /// TOML file of tank definitions
+    #[arg(long, env = "DIATOM_DEFINITIONS")]
+    definitions: Option<PathBuf>,
+    /// Rooms open at once, including the ones from the config file
+    #[arg(long, env = "DIATOM_MAX_ROOMS")]
+    max_rooms: Option<usize>,
+    /// Seconds a room opened by a client stays open once everyone left
+    #[arg(long, env = "DIATOM_EMPTY_ROOM_TIMEOUT")]
+    empty_room_timeout: Option<f64>,
+    /// Bearer token of the /admin endpoints, prefer the variable over the flag
+    #[arg(long, env = "DIATOM_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
+    /// WebSocket connections open at once from one address
+    #[arg(long, env = "DIATOM_MAX_CONNECTIONS_PER_IP")]
+    max_connections_per_ip: Option<usize>,
+    /// Origins of the pages allowed to connect, separated by commas
+    #[arg(long, env = "DIATOM_ALLOWED_ORIGINS", value_delimiter = ',')]
+    allowed_origins: Option<Vec<String>>,
+    /// TOML file the ban list is kept in
+    #[arg(long, env = "DIATOM_BANS_FILE")]
+    bans_file: Option<PathBuf>,
+    /// Folder each room is recorded to
+    #[arg(long, env = "DIATOM_RECORD_DIR")]
+    record_dir: Option<PathBuf>,
+    /// Replay file played to every client instead of running any room
+    #[arg(long, env = "DIATOM_REPLAY")]
+    replay: Option<PathBuf>,
+    /// How many times faster than it was recorded the replay is played
+    #[arg(long, env = "DIATOM_REPLAY_SPEED")]
+    replay_speed: Option<f64>,
+    /// Lowest level logged, or per module directives
+    #[arg(long, env = "DIATOM_LOG_LEVEL")]
+    log_level: Option<String>,
+    #[arg(long, env = "DIATOM_LOG_FORMAT")]
+    log_format: Option<LogFormat>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    /// A field with a value the server can't run with
+    Invalid(&'static str, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "could not read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "could not parse {}: {}", path.display(), e),
+            ConfigError::Invalid(field, reason) => write!(f, "invalid `{}`: {}", field, reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Read and deserialize a TOML file
+pub fn read_toml<T: DeserializeOwned>(path: &Path) -> Result<T, ConfigError> {
+    let text = fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+    toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+}
+
+impl ServerConfig {
+    /// Build the config from the file, environment and command line
+    ///
+    /// Exits with a usage message if a flag or variable can't be parsed.
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::from_args(Args::parse())
+    }
+
+    /// Build the config from the file given in `args` with `args` on top, variables included
+    fn from_args(args: Args) -> Result<Self, ConfigError> {
+        let mut config = match &args.config {
+            Some(path) => read_toml(path)?,
+            None if Path::new(DEFAULT_CONFIG).exists() => read_toml(Path::new(DEFAULT_CONFIG))?,
+            None => ServerConfig::default(),
+        };
+
+        config.bind = args.bind.unwrap_or(config.bind);
+        config.port = args.port.unwrap_or(config.port);
+        config.tick_rate = args.tick_rate.unwrap_or(config.tick_rate);
+        config.snapshot_rate = args.snapshot_rate.unwrap_or(config.snapshot_rate);
+        config.map_width = args.map_width.unwrap_or(config.map_width);
+        config.map_height = args.map_height.unwrap_or(config.map_height);
+        config.max_players = args.max_players.unwrap_or(config.max_players);
+        config.max_spectators = args.max_spectators.unwrap_or(config.max_spectators);
+        config.chat_filter = args.chat_filter.unwrap_or(config.chat_filter);
+        config.resume_grace = args.resume_grace.unwrap_or(config.resume_grace);
+        config.definitions = args.definitions.or(config.definitions);
+        config.max_rooms = args.max_rooms.unwrap_or(config.max_rooms);
+        config.empty_room_timeout = args.empty_room_timeout.unwrap_or(config.empty_room_timeout);
+        config.admin_token = args.admin_token.or(config.admin_token);
+        config.max_connections_per_ip = args.max_connections_per_ip.unwrap_or(config.max_connections_per_ip);
+        config.allowed_origins = args.allowed_origins.unwrap_or(config.allowed_origins);
+        config.bans_file = args.bans_file.or(config.bans_file);
+        config.record_dir = args.record_dir.or(config.record_dir);
+        config.replay = args.replay.or(config.replay);
+        config.replay_speed = args.replay_speed.unwrap_or(config.replay_speed);
+        config.log_level = args.log_level.unwrap_or(config.log_level);
+        config.log_format = args.log_format.unwrap_or(config.log_format);
+
+        config.validate()?;
+        config.validate_rooms()?;
+        Ok(config)
+    }
+
+    /// The config a room's game runs with, the server's with the room's settings on top
+    pub fn for_room(&self, room: &RoomConfig) -> ServerConfig {
+        ServerConfig {
+            map_width: room.map_width.unwrap_or(self.map_width),
+            map_height: room.map_height.unwrap_or(self.map_height),
+            max_players: room.max_players.unwrap_or(self.max_players),
+            rooms: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    fn validate_rooms(&self) -> Result<(), ConfigError> {
+        if self.rooms.is_empty() {
+            return Err(ConfigError::Invalid("rooms", "at least one room is needed".to_string()));
+        }
+        if self.max_rooms < self.rooms.len() {
+            return Err(ConfigError::Invalid(
+                "max_rooms",
+                format!("{} is less than the {} configured rooms", self.max_rooms, self.rooms.len()),
+            ));
+        }
+        if !self.empty_room_timeout.is_finite() || self.empty_room_timeout <= 0.0 {
+            return Err(ConfigError::Invalid(
+                "empty_room_timeout",
+                format!("{} is not a positive number of seconds", self.empty_room_timeout),
+            ));
+        }
+
+        for (i, room) in self.rooms.iter().enumerate() {
+            if !valid_room_name(&room.name) {
+                return Err(ConfigError::Invalid(
+                    "rooms",
+                    format!("`{}` is not 1 to 32 letters, digits, - or _", room.name),
+                ));
+            }
+            if self.rooms[..i].iter().any(|other| other.name == room.name) {
+                return Err(ConfigError::Invalid(
+                    "rooms",
+                    format!("`{}` is there twice", room.name),
+                ));
+            }
+            self.for_room(room).validate().map_err(|e| match e {
+                ConfigError::Invalid(field, reason) => {
+                    ConfigError::Invalid(field, format!("{} in room `{}`", reason, room.name))
+                }
+                e => e,
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let positive = |field, value: f64| {
+            if value.is_finite() && value > 0.0 {
+                Ok(())
+            } else {
+                Err(ConfigError::Invalid(field, format!("{} is not a positive number", value)))
+            }
+        };
+
+        positive("tick_rate", self.tick_rate)?;
+        positive("snapshot_rate", self.snapshot_rate)?;
+        positive("map_width", self.map_width)?;
+        positive("map_height", self.map_height)?;
+        positive("replay_speed", self.replay_speed)?;
+
+        if self.tick_rate > 1000.0 {
+            return Err(ConfigError::Invalid(
+                "tick_rate",
+                format!("{} is more than 1000 updates per second", self.tick_rate),
+            ));
+        }
+        if self.snapshot_rate > self.tick_rate {
+            return Err(ConfigError::Invalid(
+                "snapshot_rate",
+                format!(
+                    "{} is more than the tick rate of {}",
+                    self.snapshot_rate, self.tick_rate
+                ),
+            ));
+        }
+        if !self.resume_grace.is_finite() || self.resume_grace < 0.0 {
+            return Err(ConfigError::Invalid(
+                "resume_grace",
+                format!("{} is not a number of seconds", self.resume_grace),
+            ));
+        }
+        if self.admin_token.as_ref().is_some_and(|token| token.len() < 16) {
+            return Err(ConfigError::Invalid(
+                "admin_token",
+                "must be at least 16 characters".to_string(),
+            ));
+        }
+        // Messages are masked word by word, so anything else would never match
+        if let Some(word) = self
+            .chat_filter
+            .iter()
+            .find(|word| word.is_empty() || !word.chars().all(char::is_alphanumeric))
+        {
+            return Err(ConfigError::Invalid(
+                "chat_filter",
+                format!("`{}` is not a single word of letters and digits", word),
+            ));
+        }
+        if self.max_connections_per_ip == 0 {
+            return Err(ConfigError::Invalid(
+                "max_connections_per_ip",
+                "must be at least 1".to_string(),
+            ));
+        }
+        // Browsers send the scheme, host and port only, so anything else would never match
+        if let Some(origin) = self
+            .allowed_origins
+            .iter()
+            .find(|origin| !origin.contains("://") || origin.ends_with('/'))
+        {
+            return Err(ConfigError::Invalid(
+                "allowed_origins",
+                format!("`{}` is not like `https://example.com`", origin),
+            ));
+        }
+        if let Err(e) = EnvFilter::try_new(&self.log_level) {
+            return Err(ConfigError::Invalid("log_level", e.to_string()));
+        }
+        if self.max_players == 0 || self.max_players > PLAYER_LIMIT {
+            return Err(ConfigError::Invalid(
+                "max_players",
+                format!("{} is not between 1 and {}", self.max_players, PLAYER_LIMIT),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Seconds between two game updates
+    pub fn tick_interval(&self) -> f64 {
+        1.0 / self.tick_rate
+    }
+
+    /// How long a disconnected player is kept
+    pub fn resume_grace(&self) -> Duration {
+        Duration::from_secs_f64(self.resume_grace)
+    }
+
+    /// How long a room opened by a client stays open once everyone left
+    pub fn empty_room_timeout(&self) -> Duration {
+        Duration::from_secs_f64(self.empty_room_timeout)
+    }
+
+    /// Game updates between two snapshots
+    pub fn ticks_per_snapshot(&self) -> u32 {
+        (self.tick_rate / self.snapshot_rate).round().max(1.0) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn invalid_field(config: ServerConfig) -> Option<&'static str> {
+        match config.validate() {
+            Err(ConfigError::Invalid(field, _)) => Some(field),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn overrides_apply_file_then_env_then_cli() {
+        let path = env::temp_dir().join(format!("diatom-config-test-{}.toml", std::process::id()));
+        fs::write(&path, "port = 4000\ntick_rate = 50\nsnapshot_rate = 10\nmap_width = 100\n").unwrap();
+        // The only test that reads these variables
+        env::set_var("DIATOM_TICK_RATE", "40");
+        env::set_var("DIATOM_MAP_WIDTH", "200");
+
+        let args = Args::try_parse_from([
+            "diatom-server".as_ref(),
+            "--config".as_ref(),
+            path.as_os_str(),
+            "--map-width".as_ref(),
+            "300".as_ref(),
+        ]);
+        env::remove_var("DIATOM_TICK_RATE");
+        env::remove_var("DIATOM_MAP_WIDTH");
+        let config = ServerConfig::from_args(args.unwrap());
+        fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+
+        // Only in the file
+        assert_eq!(config.port, 4000);
+        assert_eq!(config.snapshot_rate, 10.0);
+        // The variable wins over the file
+        assert_eq!(config.tick_rate, 40.0);
+        // The flag wins over both
+        assert_eq!(config.map_width, 300.0);
+        // In none of them
+        assert_eq!(config.map_height, ServerConfig::default().map_height);
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        assert!(ServerConfig::default().validate().is_ok());
+        assert!(ServerConfig::default().validate_rooms().is_ok());
+    }
+
+    #[test]
+    fn snapshot_rate_above_tick_rate_is_rejected() {
+        let config = ServerConfig {
+            tick_rate: 30.0,
+            snapshot_rate: 60.0,
+            ..ServerConfig::default()
+        };
+        assert_eq!(invalid_field(config), Some("snapshot_rate"));
+    }
+
+    #[test]
+    fn short_admin_token_is_rejected() {
+        let config = ServerConfig {
+            admin_token: Some("too short".to_string()),
+            ..ServerConfig::default()
+        };
+        assert_eq!(invalid_field(config), Some("admin_token"));
+
+        let config = ServerConfig {
+            admin_token: Some("long enough to be guessed slowly".to_string()),
+            ..ServerConfig::default()
+        };
+        assert_eq!(invalid_field(config), None);
+    }
+
+    #[test]
+    fn empty_room_timeout_must_be_positive() {
+        let config = ServerConfig {
+            empty_room_timeout: 0.0,
+            ..ServerConfig::default()
+        };
+        assert!(matches!(
+            config.validate_rooms(),
+            Err(ConfigError::Invalid("empty_room_timeout", _))
+        ));
+    }
+
+    #[test]
+    fn max_players_is_bounded() {
+        for max_players in [0, PLAYER_LIMIT + 1] {
+            let config = ServerConfig {
+                max_players,
+                ..ServerConfig::default()
+            };
+            assert_eq!(invalid_field(config), Some("max_players"));
+        }
+        let config = ServerConfig {
+            max_players: PLAYER_LIMIT,
+            ..ServerConfig::default()
+        };
+        assert_eq!(invalid_field(config), None);
+    }
+}
@@ -1,14 +1,26 @@
-use serde_json::{json, Value};
-use std::f64::consts::PI;
+use crate::config::{read_toml, ConfigError};
+use diatom_protocol::{GunMockup, Mockup};
+use serde::Deserialize;
+use std::{f64::consts::PI, path::Path};
+use tracing::info;
+
+/// ## A definitions file
+/// ### [[definitions]] tables, each with a `type` of "Unit" or "Building"
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DefinitionsFile {
+    definitions: Vec<Definition>,
+}
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type")]
 pub enum Definition {
     #[allow(dead_code)]
     Building(Building),
     Unit(Unit),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Building {
     label: String,
     body: Body,
@@ -17,7 +29,7 @@ pub struct Building {
     guns: Vec<Gun>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Unit {
     label: String,
     body: Body,
@@ -28,12 +40,12 @@ pub struct Unit {
     guns: Vec<Gun>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
 struct Body {
     health: u8,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
 struct Gun {
     color: String,
     shape: u8,
@@ -48,7 +60,6 @@ struct Gun {
 fn create_defs() -> Vec<Definition> {
     let mut definitions = Vec::new();
     let gray = "#808080".to_string();
-    let time = std::time::Instant::now();
 
     definitions.push(Definition::Unit(Unit {
         label: "Aggressor".to_string(),
@@ -71,47 +82,73 @@ fn create_defs() -> Vec<Definition> {
         ],
     }));
 
-    println!("Mockups loaded in {:?}", time.elapsed());
-
     definitions
 }
 
-pub fn generate_mockups() -> Value {
-    let defs = create_defs();
+/// The definitions from a file, or the built-in ones
+pub fn load_definitions(path: Option<&Path>) -> Result<Vec<Definition>, ConfigError> {
+    let time = std::time::Instant::now();
+    let definitions = match path {
+        Some(path) => read_toml::<DefinitionsFile>(path)?.definitions,
+        None => create_defs(),
+    };
 
-    let mut index = 0;
+    if definitions.is_empty() {
+        return Err(ConfigError::Invalid("definitions", "no definitions found".to_string()));
+    }
 
-    let mut mockups: Vec<Value> = Vec::new();
-    for def in &defs {
-        let mut guns: Vec<Value> = Vec::new();
+    info!(count = definitions.len(), "definitions loaded in {:?}", time.elapsed());
 
-        match def {
-            Definition::Building(b) => {
-                for gun in &b.guns {
-                    let off = Vector::new(gun.x, gun.y);
-                    let direction = off.direction();
-                    let offset = off.length();
-
-                    guns.push(json!({"color": gun.color, "shape": gun.shape, "offset": offset, "direction": direction, "width": gun.width, "height": gun.height, "angle": gun.angle * PI / 180.0, "aspect": gun.aspect}));
-                }
-                mockups.push(json!({"index": index, "label": b.label, "color": b.color, "size": b.size, "health": b.body.health, "guns": guns}));
-            }
-            Definition::Unit(u) => {
-                for gun in &u.guns {
-                    let off = Vector::new(gun.x, gun.y);
-                    let direction = off.direction();
-                    let offset = off.length();
-
-                    guns.push(json!({"color": gun.color, "shape": gun.shape, "offset": offset, "direction": direction, "width": gun.width, "height": gun.height, "angle": gun.angle * PI / 180.0, "aspect": gun.aspect}));
-                }
-                mockups.push(json!({"index": index, "label": u.label, "color": u.color, "shape": u.shape, "width": u.width, "height": u.height, "health": u.body.health, "guns": guns}));
+    Ok(definitions)
+}
+
+fn gun_mockups(guns: &[Gun]) -> Vec<GunMockup> {
+    guns.iter()
+        .map(|gun| {
+            let off = Vector::new(gun.x, gun.y);
+
+            GunMockup {
+                offset: off.length(),
+                direction: off.direction(),
+                width: gun.width,
+                height: gun.height,
+                angle: gun.angle * PI / 180.0,
+                aspect: gun.aspect,
+                color: gun.color.clone(),
+                shape: gun.shape,
             }
-        }
+        })
+        .collect()
+}
 
-        index += 1;
+pub fn generate_mockups(defs: &[Definition]) -> Vec<Mockup> {
+    let mut mockups: Vec<Mockup> = Vec::new();
+    for (index, def) in defs.iter().enumerate() {
+        match def {
+            Definition::Building(b) => mockups.push(Mockup {
+                index: index as u16,
+                label: b.label.clone(),
+                color: b.color.clone(),
+                shape: 0,
+                width: b.size as f64,
+                height: b.size as f64,
+                health: b.body.health,
+                guns: gun_mockups(&b.guns),
+            }),
+            Definition::Unit(u) => mockups.push(Mockup {
+                index: index as u16,
+                label: u.label.clone(),
+                color: u.color.clone(),
+                shape: u.shape,
+                width: u.width,
+                height: u.height,
+                health: u.body.health,
+                guns: gun_mockups(&u.guns),
+            }),
+        }
     }
 
-    json!(mockups)
+    mockups
 }
 
 struct Vector {
@@ -1,18 +1,21 @@
-use crate::CLIENT_COUNTER;
-
-use super::{game::Game, rect::Rectangle};
+use super::{game::Game, id::EntityId, rect::Rectangle};
 use std::collections::HashMap;
+use tracing::trace;
 
 pub struct EntityState {
-    pub id: u16,
+    pub id: EntityId,
     pub mockup_id: u16,
     pub bounds: Rectangle,
     pub angle: f64,
+    /// Nickname of a player that picked one
+    pub name: Option<String>,
 }
 
 #[allow(dead_code)]
 pub trait Entity: CloneEntity {
-    fn id(&self) -> u16;
+    fn id(&self) -> EntityId;
+    /// What the entity is, entities are counted by kind in the metrics
+    fn kind(&self) -> &'static str;
     fn mockup_id(&self) -> u16;
     fn bounds(&self) -> Rectangle;
     fn set_vel(&mut self, vx: f64, vy: f64);
@@ -20,16 +23,16 @@ pub trait Entity: CloneEntity {
     fn remove(&mut self, game: &mut Game);
     fn get_state(&self) -> EntityState;
     fn update_pos(&mut self);
-    fn stay_in_bounds(&mut self, width: f64, height: f64) {}
-    fn set_bounds(&mut self, bounds: Rectangle) {}
-    fn set_keys(&mut self, keys: &HashMap<char, bool>) {}
+    fn stay_in_bounds(&mut self, _width: f64, _height: f64) {}
+    fn set_bounds(&mut self, _bounds: Rectangle) {}
+    fn set_keys(&mut self, _keys: &HashMap<char, bool>) {}
     fn is_player(&self) -> bool {
         false
     }
 }
 
 pub trait CloneEntity {
-    fn clone_foo<'a>(&self) -> Box<dyn Entity>;
+    fn clone_foo(&self) -> Box<dyn Entity>;
 }
 
 impl<T> CloneEntity for T
@@ -50,18 +53,20 @@ impl Clone for Box<dyn Entity> {
 #[derive(Clone)]
 
 pub struct Player {
-    pub index: u16,
+    pub index: EntityId,
     pub mockup_index: u16,
     pub bounds: Rectangle,
     pub vel: (f64, f64),
     pub keys: HashMap<char, bool>,
     pub angle: f64,
     pub shooting: bool,
+    /// Nickname, empty if the player didn't pick one
+    pub name: String,
 }
 
 impl Player {
     pub fn new(
-        index: u16,
+        index: EntityId,
         mockup_index: u16,
         bounds: Rectangle,
         vel: (f64, f64),
@@ -77,20 +82,17 @@ impl Player {
             keys,
             angle,
             shooting,
+            name: String::new(),
         }
     }
 
     pub fn shoot(&mut self, game: &mut Game) {
         if self.shooting {
-            println!("player {} is shooting", self.index);
-            game.id += 1;
-            unsafe {
-                CLIENT_COUNTER += 1;
-            }
+            trace!(id = self.index.get(), "shooting");
             game.spawn_entity(Box::new(Bullet::new(
-                game.id,
+                game.ids.allocate(),
                 0,
-                self.bounds.clone(),
+                self.bounds,
                 (0.0, 0.0),
                 self.angle,
             )));
@@ -112,10 +114,14 @@ impl Player {
 }
 
 impl Entity for Player {
-    fn id(&self) -> u16 {
+    fn id(&self) -> EntityId {
         self.index
     }
 
+    fn kind(&self) -> &'static str {
+        "player"
+    }
+
     fn mockup_id(&self) -> u16 {
         self.mockup_index
     }
@@ -142,6 +148,7 @@ impl Entity for Player {
             mockup_id: self.mockup_index,
             bounds: self.bounds,
             angle: self.angle,
+            name: Some(self.name.clone()).filter(|name| !name.is_empty()),
         }
     }
 
@@ -201,7 +208,7 @@ impl Entity for Player {
 
 #[derive(Clone)]
 struct Bullet {
-    id: u16,
+    id: EntityId,
     mockup_id: u16,
     bounds: Rectangle,
     vel: (f64, f64),
@@ -209,7 +216,13 @@ struct Bullet {
 }
 
 impl Bullet {
-    pub fn new(id: u16, mockup_id: u16, bounds: Rectangle, vel: (f64, f64), angle: f64) -> Self {
+    pub fn new(
+        id: EntityId,
+        mockup_id: u16,
+        bounds: Rectangle,
+        vel: (f64, f64),
+        angle: f64,
+    ) -> Self {
         Self {
             id,
             mockup_id,
@@ -221,10 +234,14 @@ impl Bullet {
 }
 
 impl Entity for Bullet {
-    fn id(&self) -> u16 {
+    fn id(&self) -> EntityId {
         self.id
     }
 
+    fn kind(&self) -> &'static str {
+        "bullet"
+    }
+
     fn mockup_id(&self) -> u16 {
         self.mockup_id
     }
@@ -251,6 +268,7 @@ impl Entity for Bullet {
             mockup_id: self.mockup_id,
             bounds: self.bounds,
             angle: self.angle,
+            name: None,
         }
     }
 
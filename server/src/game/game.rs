@@ -1,12 +1,19 @@
 use super::{
     entity::{Entity, EntityState, Player},
+    id::{EntityId, IdAllocator},
     rect::Rectangle,
 };
 use crate::game::up_search_quadtree::UpSearchQuadTree;
+use diatom_protocol::{VIEW_HEIGHT, VIEW_WIDTH};
 use rand::random;
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+use tracing::{debug, trace};
 
-// todo: entity id to u32 (or usize) because 65565 is not so big
+/// Extra room around a view so entities don't pop in at the edge of the screen
+const VIEW_MARGIN: f64 = 200.0;
 
 #[derive(Copy, Clone)]
 pub struct Map {
@@ -15,34 +22,49 @@ pub struct Map {
 }
 
 pub struct GameState {
+    pub tick: u32,
     pub entities: Vec<EntityState>,
     pub map: Map,
+    /// Ids of the entities each player and spectator can see
+    pub views: HashMap<EntityId, Vec<EntityId>>,
+}
+
+/// ## Where a spectator looks
+///
+/// A spectator following an entity goes back to a free camera where the entity was last seen
+/// once it's gone.
+struct Spectator {
+    follow: Option<EntityId>,
+    x: f64,
+    y: f64,
 }
 
 pub struct Game {
-    pub id: u16,
-    entities: HashMap<u16, Box<dyn Entity>>,
-    players: HashMap<u16, Player>,
+    /// Shared with the connections, which take their ids from it too
+    pub ids: Arc<IdAllocator>,
+    pub tick: u32,
+    entities: HashMap<EntityId, Box<dyn Entity>>,
+    players: HashMap<EntityId, Player>,
+    /// By the id of their connection, which no entity has
+    spectators: HashMap<EntityId, Spectator>,
     pub map: Map,
-    quadtree: UpSearchQuadTree<u16, 8>,
+    quadtree: UpSearchQuadTree<EntityId, 8>,
 }
 
 impl Game {
-    pub fn new() -> Self {
-        let map = Map {
-            width: 40.0 * 32.0,
-            height: 40.0 * 32.0,
-        };
+    pub fn new(map: Map, ids: Arc<IdAllocator>) -> Self {
         Self {
-            id: 0,
+            ids,
+            tick: 0,
             entities: HashMap::new(),
             players: HashMap::new(),
+            spectators: HashMap::new(),
             map,
             quadtree: UpSearchQuadTree::new(Rectangle::new(0.0, 0.0, map.width, map.height)),
         }
     }
 
-    pub fn add_player(&mut self, id: u16) {
+    pub fn add_player(&mut self, id: EntityId, name: String) {
         let size = 65.0;
         let bounds = Rectangle::center_rect(
             random::<f64>() * self.map.width,
@@ -50,34 +72,122 @@ impl Game {
             size,
             size,
         );
-        let entity = Box::new(Player::new(
-            id,
-            0,
-            bounds.clone(),
-            (0.0, 0.0),
-            HashMap::from([('w', false), ('a', false), ('s', false), ('d', false)]),
-            0.0,
-            false,
-        ));
-        self.id += 1;
+        let entity = Box::new(Player {
+            name,
+            ..Player::new(
+                id,
+                0,
+                bounds,
+                (0.0, 0.0),
+                HashMap::from([('w', false), ('a', false), ('s', false), ('d', false)]),
+                0.0,
+                false,
+            )
+        });
+        let (x, y) = bounds.get_center();
+        debug!(player = id.get(), name = %entity.name, x, y, "player spawned");
         self.players.insert(id, *entity.clone());
         self.spawn_entity(entity);
     }
 
+    pub fn player_count(&self) -> usize {
+        self.players.len()
+    }
+
+    /// How many entities of each kind there are
+    pub fn entity_counts(&self) -> BTreeMap<&'static str, usize> {
+        let mut counts = BTreeMap::new();
+        for entity in self.entities.values() {
+            *counts.entry(entity.kind()).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Start watching from the middle of the map
+    pub fn add_spectator(&mut self, id: EntityId) {
+        let spectator = Spectator {
+            follow: None,
+            x: self.map.width / 2.0,
+            y: self.map.height / 2.0,
+        };
+        self.spectators.insert(id, spectator);
+    }
+
+    /// Returns `false` if there was no such spectator
+    pub fn remove_spectator(&mut self, id: EntityId) -> bool {
+        let removed = self.spectators.remove(&id).is_some();
+        if removed {
+            self.ids.release(id);
+        }
+        removed
+    }
+
+    pub fn spectator_count(&self) -> usize {
+        self.spectators.len()
+    }
+
+    /// Make a spectator's camera follow an entity, ignored if there's no such entity
+    pub fn follow(&mut self, id: EntityId, target: EntityId) {
+        let Some(spectator) = self.spectators.get_mut(&id) else {
+            return;
+        };
+        if let Some(entity) = self.entities.get(&target) {
+            (spectator.x, spectator.y) = entity.bounds().get_center();
+            spectator.follow = Some(target);
+        }
+    }
+
+    /// Move a spectator's free camera, it stops following anything
+    pub fn move_camera(&mut self, id: EntityId, x: f64, y: f64) {
+        let Some(spectator) = self.spectators.get_mut(&id) else {
+            return;
+        };
+        if x.is_finite() && y.is_finite() {
+            spectator.follow = None;
+            spectator.x = x.clamp(0.0, self.map.width);
+            spectator.y = y.clamp(0.0, self.map.height);
+        }
+    }
+
+    pub fn has_player(&self, id: EntityId) -> bool {
+        self.players.contains_key(&id)
+    }
+
+    /// Every player with its nickname and the center of its tank
+    pub fn player_positions(&self) -> Vec<(EntityId, &str, f64, f64)> {
+        self.players
+            .iter()
+            .filter_map(|(&id, player)| {
+                let (x, y) = self.entities.get(&id)?.bounds().get_center();
+                Some((id, player.name.as_str(), x, y))
+            })
+            .collect()
+    }
+
+    /// Every spectator with where its camera is
+    pub fn spectator_positions(&self) -> Vec<(EntityId, f64, f64)> {
+        self.spectators
+            .iter()
+            .map(|(&id, spectator)| (id, spectator.x, spectator.y))
+            .collect()
+    }
+
     pub fn spawn_entity(&mut self, entity: Box<dyn Entity>) {
         let id = entity.id();
         self.quadtree.insert(entity.bounds(), id);
         self.entities.insert(id, entity);
     }
 
-    pub fn remove_entity_at_id(&mut self, id: u16) {
-        if let Some(_entity) = self.entities.remove(&id) {
+    pub fn remove_entity_at_id(&mut self, id: EntityId) {
+        if let Some(entity) = self.entities.remove(&id) {
+            trace!(id = id.get(), kind = entity.kind(), "entity removed");
             self.quadtree.remove(id);
+            self.ids.release(id);
         }
         self.players.remove(&id);
     }
 
-    pub fn set_input(&mut self, id: u16, key: u8, value: bool) {
+    pub fn set_input(&mut self, id: EntityId, key: u8, value: bool) {
         if let Some(entity) = self.players.get_mut(&id) {
             let char = match key {
                 0 => 'w',
@@ -91,24 +201,34 @@ impl Game {
         }
     }
 
-    pub fn set_mouse(&mut self, id: u16, rad: f64) {
+    pub fn set_mouse(&mut self, id: EntityId, rad: f64) {
         if let Some(entity) = self.players.get_mut(&id) {
             entity.input_angle(rad);
         }
     }
 
-    pub fn set_mouse_click(&mut self, id: u16, b: bool) {
+    pub fn set_mouse_click(&mut self, id: EntityId, b: bool) {
         if let Some(entity) = self.players.clone().get_mut(&id) {
             entity.input_click(b);
             entity.shoot(self);
         }
     }
 
+    /// Release every key and the mouse of a player whose client went away
+    pub fn clear_input(&mut self, id: EntityId) {
+        for key in 0..4 {
+            self.set_input(id, key, false);
+        }
+        self.set_mouse_click(id, false);
+    }
+
     pub fn update(&mut self) {
+        self.tick += 1;
+
         let mut players_immut = HashMap::new();
         players_immut.clone_from(&self.entities);
 
-        let ids = self.entities.keys().cloned().collect::<Vec<u16>>();
+        let ids = self.entities.keys().cloned().collect::<Vec<EntityId>>();
 
         for id in ids {
             let entity = self.entities.get_mut(&id).unwrap();
@@ -116,9 +236,9 @@ impl Game {
             entity.update_pos();
             entity.stay_in_bounds(self.map.width, self.map.height);
 
-            let mut candidates: Vec<u16> = Vec::new();
+            let mut candidates: Vec<EntityId> = Vec::new();
 
-            self.quadtree.search(&entity.bounds(), |id: u16| {
+            self.quadtree.search(&entity.bounds(), |id: EntityId| {
                 candidates.push(id);
             });
 
@@ -138,16 +258,61 @@ impl Game {
 
             self.quadtree.update(entity.bounds(), id);
         }
+
+        for spectator in self.spectators.values_mut() {
+            let Some(target) = spectator.follow else {
+                continue;
+            };
+            match self.entities.get(&target) {
+                Some(entity) => (spectator.x, spectator.y) = entity.bounds().get_center(),
+                None => spectator.follow = None,
+            }
+        }
+    }
+
+    /// The area around a player or a spectator's camera that gets sent to its client
+    pub fn view(&self, id: EntityId) -> Option<Rectangle> {
+        let (x, y) = match self.spectators.get(&id) {
+            Some(spectator) => (spectator.x, spectator.y),
+            None => self.entities.get(&id)?.bounds().get_center(),
+        };
+        Some(Rectangle::center_rect(
+            x,
+            y,
+            VIEW_WIDTH + VIEW_MARGIN * 2.0,
+            VIEW_HEIGHT + VIEW_MARGIN * 2.0,
+        ))
+    }
+
+    /// Ids of the entities overlapping a player or spectator's view
+    pub fn visible_entities(&self, id: EntityId) -> Vec<EntityId> {
+        let mut visible = Vec::new();
+        if let Some(view) = self.view(id) {
+            self.quadtree.search(&view, |candidate: EntityId| {
+                // The quadtree is loose, so candidates may be just outside the view
+                if let Some(entity) = self.entities.get(&candidate) {
+                    if view.intersects(&entity.bounds()) {
+                        visible.push(candidate);
+                    }
+                }
+            });
+        }
+        visible
     }
 
     pub fn get_state(&self) -> GameState {
         let mut state = GameState {
+            tick: self.tick,
             entities: Vec::new(),
             map: self.map,
+            views: HashMap::new(),
         };
         for entity in self.entities.values() {
             state.entities.push(entity.get_state());
         }
+        for &id in self.players.keys().chain(self.spectators.keys()) {
+            state.views.insert(id, self.visible_entities(id));
+        }
         state
     }
 }
@@ -0,0 +1,136 @@
+use std::{collections::HashSet, fmt, sync::Mutex};
+
+/// ## Identifies an entity, and the connection controlling it if it's a player
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EntityId(u32);
+
+impl EntityId {
+    /// An id no entity has, for clients with nothing of their own in the game
+    pub const NONE: EntityId = EntityId(0);
+
+    pub fn get(self) -> u32 {
+        self.0
+    }
+}
+
+/// An id that came from outside, like the admin API, it may not belong to anything
+impl From<u32> for EntityId {
+    fn from(id: u32) -> Self {
+        Self(id)
+    }
+}
+
+impl fmt::Display for EntityId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+struct Ids {
+    next: u32,
+    live: HashSet<u32>,
+}
+
+/// ## Hands out every [`EntityId`]
+///
+/// Shared between the game and the connection tasks. Ids count up and wrap around, skipping
+/// the ones still in use, so a live id is never handed out twice and a released one only comes
+/// back after every other `u32` was used. 0 is never handed out, see [`EntityId::NONE`].
+pub struct IdAllocator {
+    ids: Mutex<Ids>,
+}
+
+impl IdAllocator {
+    pub fn new() -> Self {
+        Self::starting_at(1)
+    }
+
+    fn starting_at(next: u32) -> Self {
+        Self {
+            ids: Mutex::new(Ids {
+                next,
+                live: HashSet::new(),
+            }),
+        }
+    }
+
+    pub fn allocate(&self) -> EntityId {
+        let mut ids = self.ids.lock().unwrap();
+        assert!(ids.live.len() < u32::MAX as usize, "every entity id is in use");
+
+        loop {
+            let id = ids.next;
+            ids.next = id.wrapping_add(1);
+            if id != EntityId::NONE.0 && ids.live.insert(id) {
+                return EntityId(id);
+            }
+        }
+    }
+
+    /// Give an id back once nothing refers to it anymore
+    pub fn release(&self, id: EntityId) {
+        self.ids.lock().unwrap().live.remove(&id.0);
+    }
+
+    /// Ids handed out and not released yet
+    pub fn live(&self) -> usize {
+        self.ids.lock().unwrap().live.len()
+    }
+}
+
+impl Default for IdAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn ids_are_unique_across_threads() {
+        let ids = Arc::new(IdAllocator::new());
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let ids = ids.clone();
+                thread::spawn(move || (0..10_000).map(|_| ids.allocate()).collect::<Vec<_>>())
+            })
+            .collect();
+
+        let mut seen = HashSet::new();
+        for thread in threads {
+            for id in thread.join().unwrap() {
+                assert!(seen.insert(id), "{} was handed out twice", id);
+            }
+        }
+        assert_eq!(seen.len(), 80_000);
+        assert_eq!(ids.live(), 80_000);
+    }
+
+    #[test]
+    fn released_ids_are_not_reused_right_away() {
+        let ids = IdAllocator::new();
+        let first = ids.allocate();
+        ids.release(first);
+
+        for _ in 0..100_000 {
+            assert_ne!(ids.allocate(), first);
+        }
+    }
+
+    #[test]
+    fn wrapping_skips_live_ids() {
+        let ids = IdAllocator::starting_at(u32::MAX - 1);
+        let live: Vec<_> = (0..3).map(|_| ids.allocate().get()).collect();
+        // 0 is skipped too
+        assert_eq!(live, [u32::MAX - 1, u32::MAX, 1]);
+
+        // Come back around to the ids still in use, they have to be stepped over
+        ids.release(EntityId(u32::MAX));
+        ids.ids.lock().unwrap().next = u32::MAX - 1;
+        assert_eq!(ids.allocate().get(), u32::MAX);
+        assert_eq!(ids.allocate().get(), 2);
+    }
+}
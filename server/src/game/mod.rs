@@ -1,5 +1,7 @@
 pub mod entity;
+#[allow(clippy::module_inception)]
 pub mod game;
+pub mod id;
 pub mod rect;
 pub mod up_search_quadtree;
 pub mod definitions;
\ No newline at end of file
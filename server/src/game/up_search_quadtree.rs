@@ -89,7 +89,7 @@ impl<T: Copy + Eq + Hash, const MAX_LEVEL: u8> UpSearchQuadTree<T, MAX_LEVEL> {
             root_bounds = Rectangle::center_rect(center_x, center_y, root_width, root_height)
         } else {
             (center_x, center_y) = world_bounds.get_center();
-            root_bounds = world_bounds.clone();
+            root_bounds = world_bounds;
         }
 
         // Initialize all nodes
@@ -103,7 +103,7 @@ impl<T: Copy + Eq + Hash, const MAX_LEVEL: u8> UpSearchQuadTree<T, MAX_LEVEL> {
         );
         grids[1][0][0].write(root);
 
-        let grids = unsafe { transmute::<_, Grids<T>>(grids) };
+        let grids = unsafe { transmute::<Vec<Grid<MaybeUninit<UpSearchQuadTreeNode<T>>>>, Grids<T>>(grids) };
 
         UpSearchQuadTree {
             grids,
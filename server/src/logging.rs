@@ -0,0 +1,16 @@
+use crate::config::{LogFormat, ServerConfig};
+use tracing_subscriber::EnvFilter;
+
+/// Send the server's tracing events to stdout, at the configured level and format
+///
+/// The level was checked by [`ServerConfig::load`], so it always parses here.
+pub fn init(config: &ServerConfig) {
+    let filter = EnvFilter::try_new(&config.log_level).unwrap_or_else(|_| EnvFilter::new("info"));
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match config.log_format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Pretty => subscriber.pretty().init(),
+        LogFormat::Json => subscriber.json().with_current_span(true).init(),
+    }
+}
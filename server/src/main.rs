@@ -1,66 +1,242 @@
+mod config;
 mod game;
+mod logging;
 mod network;
 
-use futures_util::stream::SplitSink;
+use diatom_protocol::{Snapshot, SNAPSHOT_HISTORY};
 use game::{
-    definitions::generate_mockups,
-    game::{Game, GameState}
+    definitions::{generate_mockups, load_definitions},
+    game::{Game, GameState},
+    id::{EntityId, IdAllocator}
 };
-use network::{events::*, messages::*, protocol::Message, server::*};
+use config::{valid_room_name, ConfigError, ServerConfig};
+use network::{
+    access::{self, Bans, ConnectionLimits, ConnectionSlot},
+    admin,
+    limits::{dropped_inputs, MAX_SOCKET_FRAME_SIZE},
+    metrics,
+    replay::{Replay, Viewers},
+    rooms::{close_empty_rooms, Rooms},
+    server::*,
+    writer::Outbox
+};
+use serde::Deserialize;
 use std::{
-    net::{Ipv4Addr, SocketAddrV4}, thread
+    collections::{HashMap, VecDeque},
+    net::{IpAddr, SocketAddr}, process, sync::Arc, time::Duration
 };
-use tokio::sync::mpsc::unbounded_channel;
-use warp::{
-    ws::{Message as SocketMessage, WebSocket},
-    Filter
+use tokio::{
+    signal,
+    task::JoinHandle,
+    time::timeout
 };
+use tracing::{error, info};
+use warp::{filters::BoxedFilter, Filter};
+
+/// Close reason clients see when the server stops
+const SHUTDOWN_REASON: &str = "Server restarting";
 
-const PORT: u16 = 3000;
-static mut CLIENT_COUNTER: u16 = 0;
+/// How long clients and the game threads get to finish once the server stops
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() {
-    let mockups = generate_mockups();
+    let config = or_exit(ServerConfig::load());
+    logging::init(&config);
+    let definitions = or_exit(load_definitions(config.definitions.as_deref()));
+    let bans = Arc::new(or_exit(Bans::load(config.bans_file.clone())));
+    let limits = Arc::new(ConnectionLimits::new(config.max_connections_per_ip));
+    let origins = config.allowed_origins.clone();
+    let mockups = generate_mockups(&definitions);
 
-    let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), PORT);
+    let addr = SocketAddr::new(config.bind, config.port);
 
-    let (broadcast_sender, broadcast_receiver) = unbounded_channel::<BroadcastEvent>();
-    tokio::spawn(broadcast(broadcast_receiver));
+    if let Some(path) = &config.replay {
+        let replay = Arc::new(or_exit(Replay::load(path)));
+        serve_replay(replay, config.replay_speed, addr, access::allowed(bans, limits, origins)).await;
+        return;
+    }
 
-    let (game_sender, game_receiver) = unbounded_channel::<GameEvent>();
-    thread::spawn(move || run(broadcast_sender, game_receiver));
+    let rooms = Arc::new(Rooms::new(config, Arc::new(IdAllocator::new()), mockups.clone()));
+    let closer = tokio::spawn(close_empty_rooms(rooms.clone()));
 
-    println!("WebSocket server listening on {}", addr);
+    let default_room = rooms.default_room().to_string();
+    // Rooms are picked with /ws/{room} or /ws?room={room}
+    let room = warp::path!("ws" / String)
+        .or(warp::path!("ws")
+            .and(warp::query::<RoomQuery>())
+            .map(move |query: RoomQuery| query.room.unwrap_or_else(|| default_room.clone())))
+        .unify()
+        .and_then(|room: String| async move {
+            if valid_room_name(&room) {
+                Ok(room)
+            } else {
+                Err(warp::reject::not_found())
+            }
+        });
 
+    let socket_rooms = rooms.clone();
+    let socket_bans = bans.clone();
+    let list_rooms = rooms.clone();
+    let routes = room
+        .and(access::allowed(bans.clone(), limits, origins))
+        .and(warp::ws())
+        .map(move |room: String, addr: Option<SocketAddr>, slot: Option<ConnectionSlot>, ws: warp::ws::Ws| {
+            let rooms = socket_rooms.clone();
+            let bans = socket_bans.clone();
+            ws.max_frame_size(MAX_SOCKET_FRAME_SIZE)
+                .max_message_size(MAX_SOCKET_FRAME_SIZE)
+                .on_upgrade(move |socket| async move {
+                    tokio::spawn(listen(rooms, bans, room, addr, slot, socket));
+                })
+        })
+        .or(warp::path("rooms.json")
+            .map(move || warp::reply::json(&list_rooms.list()))
+            .with(warp::cors().allow_any_origin()))
+        .or(warp::path("mockups.json")
+            .map(move || warp::reply::json(&mockups))
+            .with(warp::cors().allow_any_origin()))
+        .or(warp::path("stats.json")
+            .map(|| warp::reply::json(&HashMap::from([("dropped_inputs", dropped_inputs())]))))
+        .or(warp::path("metrics").map(|| {
+            warp::reply::with_header(metrics::render(), "content-type", "text/plain; version=0.0.4")
+        }))
+        .or(admin::routes(rooms.clone(), bans))
+        .recover(admin::recover)
+        .recover(access::recover);
+
+    let bound = warp::serve(routes).try_bind_with_graceful_shutdown(addr, shutdown_signal());
+    let (addr, server) = match bound {
+        Ok(bound) => bound,
+        Err(e) => {
+            error!(%addr, "could not listen: {}", e);
+            process::exit(1);
+        }
+    };
+    info!(%addr, "listening");
+    // Resolves once a signal came in, and new connections are refused from then on
+    server.await;
+
+    info!("shutting down");
+    closer.abort();
+    let stopped = timeout(SHUTDOWN_TIMEOUT, rooms.shutdown(SHUTDOWN_REASON));
+    if stopped.await.is_err() {
+        error!("shutdown took longer than {:?}, exiting anyway", SHUTDOWN_TIMEOUT);
+        process::exit(1);
+    }
+}
+
+/// Play a replay to every client instead of running rooms, until a signal comes in
+///
+/// Clients connect to `/ws` as usual, any room they pick gets the same replay.
+async fn serve_replay(
+    replay: Arc<Replay>,
+    speed: f64,
+    addr: SocketAddr,
+    allowed: BoxedFilter<(Option<SocketAddr>, Option<ConnectionSlot>)>,
+) {
+    let mockups = replay.header.mockups.clone();
+    let viewers = Arc::new(Viewers::default());
+    let socket_viewers = viewers.clone();
     let routes = warp::path("ws")
+        .and(allowed)
         .and(warp::ws())
-        .map(move |ws: warp::ws::Ws| {
-            let game_sender = game_sender.clone();
-            ws.on_upgrade(|socket| async {
-                tokio::spawn(listen(game_sender, socket));
-            })
+        .map(move |addr: Option<SocketAddr>, slot: Option<ConnectionSlot>, ws: warp::ws::Ws| {
+            let replay = replay.clone();
+            let viewers = socket_viewers.clone();
+            ws.max_frame_size(MAX_SOCKET_FRAME_SIZE)
+                .max_message_size(MAX_SOCKET_FRAME_SIZE)
+                .on_upgrade(move |socket| async move {
+                    viewers.spawn(replay, speed, addr, slot, socket);
+                })
         })
         .or(warp::path("mockups.json")
             .map(move || warp::reply::json(&mockups))
-            .with(warp::cors().allow_any_origin()));
+            .with(warp::cors().allow_any_origin()))
+        .recover(access::recover);
 
-    warp::serve(routes).run(addr).await;
+    let bound = warp::serve(routes).try_bind_with_graceful_shutdown(addr, shutdown_signal());
+    let (addr, server) = match bound {
+        Ok(bound) => bound,
+        Err(e) => {
+            error!(%addr, "could not listen: {}", e);
+            process::exit(1);
+        }
+    };
+    info!(%addr, speed, "playing a replay");
+    server.await;
+
+    info!("shutting down");
+    let stopped = timeout(SHUTDOWN_TIMEOUT, viewers.shutdown(SHUTDOWN_REASON));
+    if stopped.await.is_err() {
+        error!("shutdown took longer than {:?}, exiting anyway", SHUTDOWN_TIMEOUT);
+        process::exit(1);
+    }
+}
+
+#[derive(Deserialize)]
+struct RoomQuery {
+    room: Option<String>,
+}
+
+/// Resolves on Ctrl+C, or on SIGTERM on unix
+async fn shutdown_signal() {
+    let interrupt = async {
+        let _ = signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
+
+/// Stop the server with a readable message if its configuration is unusable
+///
+/// Printed rather than logged, since logging is configured by the config being loaded.
+fn or_exit<T>(result: Result<T, ConfigError>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("Invalid configuration: {}", e);
+        process::exit(1);
+    })
 }
 
 struct Connection {
-    id: u16,
-    sender: SplitSink<WebSocket, SocketMessage>,
+    id: EntityId,
+    /// Address the client connected from, if warp knows it
+    ip: Option<IpAddr>,
+    /// Queues of the connection's writer task, closing it also stops its reader
+    outbox: Outbox,
+    writer: JoinHandle<()>,
+    acked_tick: Option<u32>,
+    /// Smoothed round-trip time in milliseconds, once the client answered a ping
+    rtt: Option<f64>,
+    /// What this client was sent, since every client sees a different part of the map
+    history: VecDeque<Snapshot>,
 }
 
 impl Connection {
-    fn new(sender: SplitSink<WebSocket, SocketMessage>) -> Self {
-        unsafe {
-            CLIENT_COUNTER += 1;
-            Self {
-                id: CLIENT_COUNTER,
-                sender
-            }
+    fn new(id: EntityId, ip: Option<IpAddr>, outbox: Outbox, writer: JoinHandle<()>) -> Self {
+        Self {
+            id,
+            ip,
+            outbox,
+            writer,
+            acked_tick: None,
+            rtt: None,
+            history: VecDeque::with_capacity(SNAPSHOT_HISTORY),
         }
     }
 }
@@ -0,0 +1,302 @@
+use crate::{
+    config::{read_toml, ConfigError},
+    network::admin::{error, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::task::spawn_blocking;
+use tracing::{info, warn};
+use warp::{
+    filters::BoxedFilter,
+    http::StatusCode,
+    reject::{Reject, Rejection},
+    Filter,
+};
+
+/// ## What the ban list holds, and how its file is laid out
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BanList {
+    pub ips: BTreeSet<IpAddr>,
+    /// Resume tokens, their players can't be resumed anymore
+    pub tokens: BTreeSet<String>,
+}
+
+/// ## Addresses and resume tokens that can't connect anymore
+///
+/// Read from `bans_file` at startup when it's set, and written back to it on every change.
+pub struct Bans {
+    path: Option<PathBuf>,
+    list: Arc<Mutex<BanList>>,
+    /// Held while the file is written, so an older list never overwrites a newer one
+    saving: Arc<Mutex<()>>,
+}
+
+impl Bans {
+    /// Read the ban list, a file that doesn't exist yet is an empty list
+    pub fn load(path: Option<PathBuf>) -> Result<Self, ConfigError> {
+        let list = match &path {
+            Some(path) if path.exists() => read_toml(path)?,
+            _ => BanList::default(),
+        };
+        Ok(Self {
+            path,
+            list: Arc::new(Mutex::new(list)),
+            saving: Arc::new(Mutex::new(())),
+        })
+    }
+
+    pub fn list(&self) -> BanList {
+        self.list.lock().unwrap().clone()
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        self.list.lock().unwrap().ips.contains(&ip)
+    }
+
+    pub fn is_token_banned(&self, token: &str) -> bool {
+        self.list.lock().unwrap().tokens.contains(token)
+    }
+
+    /// Returns `false` if the address was already banned
+    pub async fn ban(&self, ip: IpAddr) -> bool {
+        self.change(|list| list.ips.insert(ip)).await
+    }
+
+    /// Returns `false` if the address wasn't banned
+    pub async fn unban(&self, ip: IpAddr) -> bool {
+        self.change(|list| list.ips.remove(&ip)).await
+    }
+
+    /// Returns `false` if the token was already banned
+    pub async fn ban_token(&self, token: &str) -> bool {
+        self.change(|list| list.tokens.insert(token.to_string())).await
+    }
+
+    /// Returns `false` if the token wasn't banned
+    pub async fn unban_token(&self, token: &str) -> bool {
+        self.change(|list| list.tokens.remove(token)).await
+    }
+
+    /// Apply a change and save the list if it did anything
+    ///
+    /// The file is written from a blocking thread, without holding the list. A list that can't
+    /// be saved still applies until the server stops.
+    async fn change(&self, change: impl FnOnce(&mut BanList) -> bool) -> bool {
+        if !change(&mut self.list.lock().unwrap()) {
+            return false;
+        }
+        if let Some(path) = self.path.clone() {
+            let list = self.list.clone();
+            let saving = self.saving.clone();
+            let _ = spawn_blocking(move || {
+                let _saving = saving.lock().unwrap();
+                // The newest list, along with whatever changed since this change
+                let list = list.lock().unwrap().clone();
+                save(&path, &list);
+            })
+            .await;
+        }
+        true
+    }
+}
+
+fn save(path: &Path, list: &BanList) {
+    // Written next to the file first, so a crash never leaves half a list behind
+    let temp = path.with_extension("tmp");
+    let saved = toml::to_string(list)
+        .map_err(|e| e.to_string())
+        .and_then(|text| fs::write(&temp, text).map_err(|e| e.to_string()))
+        .and_then(|_| fs::rename(&temp, path).map_err(|e| e.to_string()));
+    if let Err(e) = saved {
+        warn!(path = %path.display(), "could not save the ban list: {}", e);
+    }
+}
+
+/// ## How many connections each address has open
+pub struct ConnectionLimits {
+    max: usize,
+    counts: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl ConnectionLimits {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            counts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a new connection, `None` if the address already has as many as it can
+    pub fn acquire(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionSlot> {
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(ip).or_insert(0);
+        if *count >= self.max {
+            return None;
+        }
+        *count += 1;
+        Some(ConnectionSlot {
+            ip,
+            limits: self.clone(),
+        })
+    }
+}
+
+/// ## A connection counted by [`ConnectionLimits`], until it's dropped
+pub struct ConnectionSlot {
+    ip: IpAddr,
+    limits: Arc<ConnectionLimits>,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut counts = self.limits.counts.lock().unwrap();
+        if let Some(count) = counts.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Banned;
+
+impl Reject for Banned {}
+
+#[derive(Debug)]
+struct ForbiddenOrigin;
+
+impl Reject for ForbiddenOrigin {}
+
+#[derive(Debug)]
+struct TooManyConnections;
+
+impl Reject for TooManyConnections {}
+
+/// The remote address of a WebSocket upgrade and its connection slot, once it passed every check
+///
+/// Banned addresses and pages from other origins are refused with a 403, and addresses with
+/// too many connections with a 429. Requests without an `Origin` header don't come from a
+/// browser and can send whatever origin they like, so they're let through.
+pub fn allowed(
+    bans: Arc<Bans>,
+    limits: Arc<ConnectionLimits>,
+    origins: Vec<String>,
+) -> BoxedFilter<(Option<SocketAddr>, Option<ConnectionSlot>)> {
+    let origins = Arc::new(origins);
+    warp::addr::remote()
+        .and(warp::header::optional::<String>("origin"))
+        .and_then(move |addr: Option<SocketAddr>, origin: Option<String>| {
+            let bans = bans.clone();
+            let limits = limits.clone();
+            let origins = origins.clone();
+            async move {
+                let ip = addr.map(|addr| addr.ip());
+                if ip.is_some_and(|ip| bans.is_banned(ip)) {
+                    info!(ip = %ip.unwrap(), "banned address refused");
+                    return Err(warp::reject::custom(Banned));
+                }
+                if let Some(origin) = origin {
+                    if !origins.is_empty()
+                        && !origins.iter().any(|allowed| allowed.eq_ignore_ascii_case(&origin))
+                    {
+                        info!(%origin, "origin refused");
+                        return Err(warp::reject::custom(ForbiddenOrigin));
+                    }
+                }
+                let slot = match ip {
+                    Some(ip) => match limits.acquire(ip) {
+                        Some(slot) => Some(slot),
+                        None => {
+                            info!(%ip, "too many connections from one address");
+                            return Err(warp::reject::custom(TooManyConnections));
+                        }
+                    },
+                    None => None,
+                };
+                Ok((addr, slot))
+            }
+        })
+        .untuple_one()
+        .boxed()
+}
+
+/// Answer the rejections of refused WebSocket upgrades, leaving the rest to warp
+pub async fn recover(rejection: Rejection) -> Result<Response, Rejection> {
+    if rejection.find::<Banned>().is_some() {
+        Ok(error(StatusCode::FORBIDDEN, "Banned"))
+    } else if rejection.find::<ForbiddenOrigin>().is_some() {
+        Ok(error(StatusCode::FORBIDDEN, "Origin not allowed"))
+    } else if rejection.find::<TooManyConnections>().is_some() {
+        Ok(error(StatusCode::TOO_MANY_REQUESTS, "Too many connections"))
+    } else {
+        Err(rejection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("diatom-{}-{}.toml", name, process::id()))
+    }
+
+    #[tokio::test]
+    async fn bans_are_saved_and_loaded_back() {
+        let path = temp_path("bans");
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let other: IpAddr = "2001:db8::1".parse().unwrap();
+
+        let bans = Bans::load(Some(path.clone())).unwrap();
+        assert!(bans.ban(ip).await);
+        assert!(!bans.ban(ip).await);
+        assert!(bans.ban(other).await);
+        assert!(bans.unban(other).await);
+        assert!(!bans.unban(other).await);
+        assert!(bans.ban_token("kept").await);
+        assert!(bans.ban_token("lifted").await);
+        assert!(bans.unban_token("lifted").await);
+
+        let loaded = Bans::load(Some(path.clone())).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(loaded.is_banned(ip));
+        assert!(!loaded.is_banned(other));
+        assert!(loaded.is_token_banned("kept"));
+        assert!(!loaded.is_token_banned("lifted"));
+        assert_eq!(loaded.list().ips.len(), 1);
+        assert_eq!(loaded.list().tokens.len(), 1);
+    }
+
+    #[test]
+    fn missing_ban_file_is_an_empty_list() {
+        let bans = Bans::load(Some(temp_path("no-bans"))).unwrap();
+        assert!(bans.list().ips.is_empty());
+        assert!(bans.list().tokens.is_empty());
+    }
+
+    #[test]
+    fn connections_per_address_are_capped() {
+        let limits = Arc::new(ConnectionLimits::new(2));
+        let ip: IpAddr = "198.51.100.1".parse().unwrap();
+
+        let first = limits.acquire(ip);
+        let second = limits.acquire(ip);
+        assert!(first.is_some() && second.is_some());
+        assert!(limits.acquire(ip).is_none());
+        // Other addresses have their own count
+        assert!(limits.acquire("198.51.100.2".parse().unwrap()).is_some());
+
+        drop(first);
+        assert!(limits.acquire(ip).is_some());
+    }
+}
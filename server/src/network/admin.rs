@@ -0,0 +1,468 @@
+use crate::{
+    game::id::EntityId,
+    network::{access::Bans, events::GameEvent, rooms::Rooms},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    net::IpAddr,
+    sync::Arc,
+};
+use tokio::sync::oneshot;
+use tracing::info;
+use warp::{
+    filters::BoxedFilter,
+    http::StatusCode,
+    reject::{Reject, Rejection},
+    reply::{json, with_status, Json, WithStatus},
+    Filter,
+};
+
+/// Longest notice an admin can send, in characters
+const MAX_NOTICE_LENGTH: usize = 200;
+
+/// Largest request body the admin API reads
+const MAX_BODY_SIZE: u64 = 4096;
+
+/// Close reason of a player kicked or banned by an admin
+const KICK_REASON: &str = "Kicked by an admin";
+const BAN_REASON: &str = "Banned by an admin";
+
+pub type Response = WithStatus<Json>;
+
+/// ## A player or spectator as listed by `/admin/players`
+#[derive(Debug, Serialize)]
+pub struct PlayerInfo {
+    id: u32,
+    name: String,
+    /// Where a spectator's camera is
+    x: f64,
+    y: f64,
+    spectator: bool,
+    /// Smoothed round-trip time in milliseconds, once the client answered a ping
+    rtt: Option<f64>,
+    address: Option<IpAddr>,
+    /// `false` while the player waits for its client to resume it
+    connected: bool,
+}
+
+impl PlayerInfo {
+    pub fn new(id: EntityId, name: &str, x: f64, y: f64) -> Self {
+        Self {
+            id: id.get(),
+            name: name.to_string(),
+            x,
+            y,
+            spectator: false,
+            rtt: None,
+            address: None,
+            connected: false,
+        }
+    }
+
+    pub fn spectator(id: EntityId, x: f64, y: f64) -> Self {
+        Self {
+            spectator: true,
+            ..Self::new(id, "", x, y)
+        }
+    }
+
+    pub fn id(&self) -> EntityId {
+        EntityId::from(self.id)
+    }
+
+    /// Fill in what the player's connection knows
+    pub fn connected(&mut self, rtt: Option<f64>, address: Option<IpAddr>) {
+        self.rtt = rtt;
+        self.address = address;
+        self.connected = true;
+    }
+}
+
+/// ## How long a room's updates take, as listed by `/admin/tick`
+#[derive(Debug, Serialize)]
+pub struct TickTime {
+    pub tick: u32,
+    /// Smoothed duration of one update in milliseconds, snapshot included
+    pub tick_time: f64,
+    /// Milliseconds an update can take at the room's tick rate
+    pub budget: f64,
+}
+
+/// ## A player or spectator kicked by an admin, as far as the server knew it
+#[derive(Debug, Default)]
+pub struct Kicked {
+    /// `None` if the player was not connected
+    pub ip: Option<IpAddr>,
+    /// `None` for spectators, which can't be resumed
+    pub token: Option<String>,
+}
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl Reject for Unauthorized {}
+
+#[derive(Deserialize)]
+struct Notice {
+    text: String,
+}
+
+/// ## The `/admin` endpoints
+///
+/// Every request needs an `Authorization: Bearer {admin_token}` header, and they all answer
+/// with JSON. They're not found at all when no token is configured.
+///
+/// - `GET /admin/players`: players and spectators of each room, with their position and
+///   round-trip time
+/// - `POST /admin/players/{id}/kick`: kick a player or spectator
+/// - `POST /admin/players/{id}/ban`: kick the player and ban its address and resume token
+/// - `GET /admin/bans`: the banned addresses and resume tokens
+/// - `POST /admin/bans/ips/{ip}`: ban an address and kick the players and spectators connected
+///   from it
+/// - `DELETE /admin/bans/ips/{ip}`
+/// - `POST /admin/bans/tokens/{token}`: refuse to resume the player with this token
+/// - `DELETE /admin/bans/tokens/{token}`
+/// - `POST /admin/notice`: `{"text": ...}` shown to every player
+/// - `GET /admin/tick`: how long each room's updates take
+pub fn routes(rooms: Arc<Rooms>, bans: Arc<Bans>) -> BoxedFilter<(Response,)> {
+    let token = rooms.config.admin_token.clone();
+    let authorized = warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let token = token.clone();
+            async move {
+                let Some(token) = token else {
+                    return Err(warp::reject::not_found());
+                };
+                let given = header.as_deref().and_then(|header| header.strip_prefix("Bearer "));
+                match given {
+                    Some(given) if same_token(given, &token) => Ok(()),
+                    _ => Err(warp::reject::custom(Unauthorized)),
+                }
+            }
+        })
+        .untuple_one();
+    let with_rooms = warp::any().map(move || rooms.clone());
+    let with_bans = warp::any().map(move || bans.clone());
+
+    // Paths are matched before the token, so other routes never see an Unauthorized rejection
+    let players = warp::path!("admin" / "players")
+        .and(warp::get())
+        .and(authorized.clone())
+        .and(with_rooms.clone())
+        .then(list_players);
+    let kick = warp::path!("admin" / "players" / u32 / "kick")
+        .and(warp::post())
+        .and(authorized.clone())
+        .and(with_rooms.clone())
+        .then(kick);
+    let ban = warp::path!("admin" / "players" / u32 / "ban")
+        .and(warp::post())
+        .and(authorized.clone())
+        .and(with_rooms.clone())
+        .and(with_bans.clone())
+        .then(ban);
+    let bans = warp::path!("admin" / "bans")
+        .and(warp::get())
+        .and(authorized.clone())
+        .and(with_bans.clone())
+        .map(|bans: Arc<Bans>| with_status(json(&bans.list()), StatusCode::OK));
+    let ban_ip = warp::path!("admin" / "bans" / "ips" / IpAddr)
+        .and(warp::post())
+        .and(authorized.clone())
+        .and(with_rooms.clone())
+        .and(with_bans.clone())
+        .then(ban_ip);
+    let unban_ip = warp::path!("admin" / "bans" / "ips" / IpAddr)
+        .and(warp::delete())
+        .and(authorized.clone())
+        .and(with_bans.clone())
+        .then(unban_ip);
+    let ban_token = warp::path!("admin" / "bans" / "tokens" / String)
+        .and(warp::post())
+        .and(authorized.clone())
+        .and(with_bans.clone())
+        .then(ban_token);
+    let unban_token = warp::path!("admin" / "bans" / "tokens" / String)
+        .and(warp::delete())
+        .and(authorized.clone())
+        .and(with_bans)
+        .then(unban_token);
+    let notice = warp::path!("admin" / "notice")
+        .and(warp::post())
+        .and(authorized.clone())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json())
+        .and(with_rooms.clone())
+        .then(notice);
+    let tick = warp::path!("admin" / "tick")
+        .and(warp::get())
+        .and(authorized)
+        .and(with_rooms)
+        .then(tick_time);
+
+    players
+        .or(kick)
+        .unify()
+        .or(ban)
+        .unify()
+        .or(bans)
+        .unify()
+        .or(ban_ip)
+        .unify()
+        .or(unban_ip)
+        .unify()
+        .or(ban_token)
+        .unify()
+        .or(unban_token)
+        .unify()
+        .or(notice)
+        .unify()
+        .or(tick)
+        .unify()
+        .boxed()
+}
+
+/// Answer the rejections of the admin API, leaving the rest to warp
+pub async fn recover(rejection: Rejection) -> Result<Response, Rejection> {
+    if rejection.find::<Unauthorized>().is_some() {
+        Ok(error(StatusCode::UNAUTHORIZED, "Missing or wrong admin token"))
+    } else {
+        Err(rejection)
+    }
+}
+
+pub fn error(status: StatusCode, message: &str) -> Response {
+    with_status(json(&BTreeMap::from([("error", message)])), status)
+}
+
+/// Compare tokens without stopping at the first difference, so timing gives nothing away
+///
+/// Their digests are compared rather than the tokens, which always have the same length, so
+/// the token's length doesn't show either.
+fn same_token(given: &str, token: &str) -> bool {
+    Sha256::digest(given)
+        .iter()
+        .zip(Sha256::digest(token).iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+async fn list_players(rooms: Arc<Rooms>) -> Response {
+    with_status(json(&room_players(&rooms).await), StatusCode::OK)
+}
+
+/// The players and spectators of every room, by room name
+async fn room_players(rooms: &Rooms) -> BTreeMap<String, Vec<PlayerInfo>> {
+    let mut waiting = Vec::new();
+    for (name, sender) in rooms.senders() {
+        let (reply, receiver) = oneshot::channel();
+        if sender.send(GameEvent::Players(reply)).is_ok() {
+            waiting.push((name, receiver));
+        }
+    }
+
+    let mut players = BTreeMap::new();
+    for (name, receiver) in waiting {
+        // A room closing in the meantime just isn't listed
+        if let Ok(list) = receiver.await {
+            players.insert(name, list);
+        }
+    }
+    players
+}
+
+/// Kick a player or spectator from whichever room it's in
+///
+/// Returns `None` if no room has it.
+async fn kick_from_rooms(rooms: &Rooms, id: EntityId, reason: &str) -> Option<Kicked> {
+    let mut waiting = Vec::new();
+    for (_, sender) in rooms.senders() {
+        let (reply, receiver) = oneshot::channel();
+        if sender.send(GameEvent::AdminKick(id, reason.to_string(), reply)).is_ok() {
+            waiting.push(receiver);
+        }
+    }
+
+    let mut kicked = None;
+    for receiver in waiting {
+        if let Ok(player) = receiver.await {
+            kicked = Some(player);
+        }
+    }
+    kicked
+}
+
+async fn kick(id: u32, rooms: Arc<Rooms>) -> Response {
+    match kick_from_rooms(&rooms, EntityId::from(id), KICK_REASON).await {
+        Some(_) => with_status(json(&BTreeMap::from([("kicked", id)])), StatusCode::OK),
+        None => error(StatusCode::NOT_FOUND, "No player with this id"),
+    }
+}
+
+async fn ban(id: u32, rooms: Arc<Rooms>, bans: Arc<Bans>) -> Response {
+    #[derive(Serialize)]
+    struct BanReply {
+        kicked: u32,
+        /// `None` if the player was not connected, so there was no address to ban
+        banned: Option<IpAddr>,
+        token: Option<String>,
+    }
+
+    match kick_from_rooms(&rooms, EntityId::from(id), BAN_REASON).await {
+        Some(kicked) => {
+            if let Some(ip) = kicked.ip {
+                bans.ban(ip).await;
+                info!(%ip, player = id, "address banned");
+            }
+            if let Some(token) = &kicked.token {
+                bans.ban_token(token).await;
+                info!(player = id, "resume token banned");
+            }
+            let reply = BanReply {
+                kicked: id,
+                banned: kicked.ip,
+                token: kicked.token,
+            };
+            with_status(json(&reply), StatusCode::OK)
+        }
+        None => error(StatusCode::NOT_FOUND, "No player with this id"),
+    }
+}
+
+async fn ban_ip(ip: IpAddr, rooms: Arc<Rooms>, bans: Arc<Bans>) -> Response {
+    #[derive(Serialize)]
+    struct BanReply {
+        banned: IpAddr,
+        kicked: Vec<u32>,
+    }
+
+    if bans.ban(ip).await {
+        info!(%ip, "address banned");
+    }
+
+    // The address is refused from now on, but its players were let in before
+    let mut kicked = Vec::new();
+    for (_, list) in room_players(&rooms).await {
+        for player in list.into_iter().filter(|player| player.address == Some(ip)) {
+            if kick_from_rooms(&rooms, player.id(), BAN_REASON).await.is_some() {
+                kicked.push(player.id);
+            }
+        }
+    }
+    with_status(json(&BanReply { banned: ip, kicked }), StatusCode::OK)
+}
+
+async fn unban_ip(ip: IpAddr, bans: Arc<Bans>) -> Response {
+    if bans.unban(ip).await {
+        info!(%ip, "address unbanned");
+        with_status(json(&BTreeMap::from([("unbanned", ip)])), StatusCode::OK)
+    } else {
+        error(StatusCode::NOT_FOUND, "This address is not banned")
+    }
+}
+
+async fn ban_token(token: String, bans: Arc<Bans>) -> Response {
+    if bans.ban_token(&token).await {
+        info!("resume token banned");
+    }
+    with_status(json(&BTreeMap::from([("banned", token)])), StatusCode::OK)
+}
+
+async fn unban_token(token: String, bans: Arc<Bans>) -> Response {
+    if bans.unban_token(&token).await {
+        info!("resume token unbanned");
+        with_status(json(&BTreeMap::from([("unbanned", token)])), StatusCode::OK)
+    } else {
+        error(StatusCode::NOT_FOUND, "This token is not banned")
+    }
+}
+
+async fn notice(notice: Notice, rooms: Arc<Rooms>) -> Response {
+    let text = notice.text.trim();
+    if text.is_empty() || text.chars().count() > MAX_NOTICE_LENGTH {
+        let message = format!("The text must be 1 to {} characters", MAX_NOTICE_LENGTH);
+        return error(StatusCode::BAD_REQUEST, &message);
+    }
+
+    let mut sent = 0;
+    for (_, sender) in rooms.senders() {
+        if sender.send(GameEvent::Notice(text.to_string())).is_ok() {
+            sent += 1;
+        }
+    }
+    info!(rooms = sent, text, "notice sent");
+    with_status(json(&BTreeMap::from([("rooms", sent)])), StatusCode::OK)
+}
+
+async fn tick_time(rooms: Arc<Rooms>) -> Response {
+    let mut waiting = Vec::new();
+    for (name, sender) in rooms.senders() {
+        let (reply, receiver) = oneshot::channel();
+        if sender.send(GameEvent::TickTime(reply)).is_ok() {
+            waiting.push((name, receiver));
+        }
+    }
+
+    let mut times = BTreeMap::new();
+    for (name, receiver) in waiting {
+        if let Ok(time) = receiver.await {
+            times.insert(name, time);
+        }
+    }
+    with_status(json(&times), StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::ServerConfig, game::id::IdAllocator};
+
+    const TOKEN: &str = "a token that is long enough";
+
+    async fn status(config: ServerConfig, authorization: Option<&str>) -> StatusCode {
+        let rooms = Arc::new(Rooms::new(config, Arc::new(IdAllocator::new()), Vec::new()));
+        let bans = Arc::new(Bans::load(None).unwrap());
+        let filter = routes(rooms.clone(), bans).recover(recover);
+
+        let mut request = warp::test::request().method("GET").path("/admin/bans");
+        if let Some(authorization) = authorization {
+            request = request.header("authorization", authorization);
+        }
+        let status = request.reply(&filter).await.status();
+        rooms.shutdown("Test over").await;
+        status
+    }
+
+    fn with_token() -> ServerConfig {
+        ServerConfig {
+            admin_token: Some(TOKEN.to_string()),
+            ..ServerConfig::default()
+        }
+    }
+
+    #[test]
+    fn tokens_are_compared_whole() {
+        assert!(same_token(TOKEN, TOKEN));
+        assert!(!same_token("a token that is long enougH", TOKEN));
+        assert!(!same_token("a token that is long enough!", TOKEN));
+        assert!(!same_token("a token", TOKEN));
+        assert!(!same_token("", TOKEN));
+    }
+
+    #[tokio::test]
+    async fn requests_need_the_token() {
+        assert_eq!(status(with_token(), None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(with_token(), Some(TOKEN)).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(with_token(), Some("Bearer wrong")).await, StatusCode::UNAUTHORIZED);
+        let right = format!("Bearer {}", TOKEN);
+        assert_eq!(status(with_token(), Some(&right)).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn routes_are_not_found_without_a_token() {
+        let right = format!("Bearer {}", TOKEN);
+        assert_eq!(status(ServerConfig::default(), Some(&right)).await, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,125 @@
+use diatom_protocol::{MAX_CHAT_LENGTH, MAX_NAME_LENGTH};
+
+/// Tidy up a chat message and mask the filtered words in it
+///
+/// Control and invisible characters are removed and the ends trimmed. `None` if nothing is
+/// left, or if it's longer than the client lets anyone type.
+pub fn clean(text: &str, filter: &[String]) -> Option<String> {
+    let text: String = text.chars().filter(|&c| !hidden(c)).collect();
+    let text = text.trim();
+    if text.is_empty() || text.chars().count() > MAX_CHAT_LENGTH {
+        return None;
+    }
+    Some(mask_words(text, filter))
+}
+
+/// Tidy up a nickname and mask the filtered words in it
+///
+/// Like a chat message, with the spaces inside it squeezed and the rest cut off past
+/// [`MAX_NAME_LENGTH`]. Empty if nothing is left, for a player without a nickname.
+pub fn clean_name(name: &str, filter: &[String]) -> String {
+    let words: Vec<String> = name
+        .split_whitespace()
+        .map(|word| word.chars().filter(|&c| !hidden(c)).collect())
+        .filter(|word: &String| !word.is_empty())
+        .collect();
+    let name: String = words.join(" ").chars().take(MAX_NAME_LENGTH).collect();
+    mask_words(name.trim_end(), filter)
+}
+
+/// Characters that can't be seen or that mess with the ones around them, like right-to-left
+/// overrides
+fn hidden(c: char) -> bool {
+    c.is_control()
+        || matches!(
+            c,
+            '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2069}' | '\u{FEFF}'
+        )
+}
+
+/// Replace each word of `text` found in `filter` with asterisks, ignoring case
+///
+/// Only whole words are masked, so a filtered word inside a longer one is left alone.
+fn mask_words(text: &str, filter: &[String]) -> String {
+    let mut masked = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(char::is_alphanumeric) {
+        masked.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = rest.find(|c: char| !c.is_alphanumeric()).unwrap_or(rest.len());
+        let word = &rest[..end];
+        let lower = word.to_lowercase();
+        if filter.iter().any(|filtered| filtered.to_lowercase() == lower) {
+            masked.extend(word.chars().map(|_| '*'));
+        } else {
+            masked.push_str(word);
+        }
+        rest = &rest[end..];
+    }
+    masked.push_str(rest);
+    masked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn length_is_counted_in_characters() {
+        // Three bytes each, a byte count would turn these away
+        let longest = "語".repeat(MAX_CHAT_LENGTH);
+        assert_eq!(clean(&longest, &[]), Some(longest.clone()));
+        assert_eq!(clean(&format!("{}語", longest), &[]), None);
+    }
+
+    #[test]
+    fn hidden_characters_are_removed() {
+        assert_eq!(clean("\u{202E}gg\u{200B} wp\u{7}\n", &[]).as_deref(), Some("gg wp"));
+        assert_eq!(clean("\u{FEFF}\u{2060}\t", &[]), None);
+        assert_eq!(clean("   ", &[]), None);
+    }
+
+    #[test]
+    fn whole_words_are_masked_ignoring_case() {
+        let filter = filter(&["Heck"]);
+        assert_eq!(clean("heck, HECK and Heck!", &filter).as_deref(), Some("****, **** and ****!"));
+        // Inside a longer word it's left alone
+        assert_eq!(clean("checks heckle", &filter).as_deref(), Some("checks heckle"));
+    }
+
+    #[test]
+    fn masking_keeps_non_ascii_words_whole() {
+        let filter = filter(&["straße"]);
+        assert_eq!(clean("STRASSE Straße", &filter).as_deref(), Some("STRASSE ******"));
+    }
+
+    #[test]
+    fn names_are_squeezed_and_cut_in_characters() {
+        assert_eq!(clean_name("  tank \u{200B}  \t lord ", &[]), "tank lord");
+        let long = "é".repeat(MAX_NAME_LENGTH + 5);
+        assert_eq!(clean_name(&long, &[]).chars().count(), MAX_NAME_LENGTH);
+    }
+
+    #[test]
+    fn names_cut_after_a_space_lose_it() {
+        let name = format!("{} b", "a".repeat(MAX_NAME_LENGTH - 1));
+        assert_eq!(clean_name(&name, &[]), "a".repeat(MAX_NAME_LENGTH - 1));
+    }
+
+    #[test]
+    fn blank_names_mean_no_nickname() {
+        for name in ["", "   ", "\t\n", "\u{200B}\u{FEFF}", " \u{202E} "] {
+            assert_eq!(clean_name(name, &[]), "", "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn names_are_masked() {
+        assert_eq!(clean_name("Heck Yeah", &filter(&["heck"])), "**** Yeah");
+    }
+}
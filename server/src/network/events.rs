@@ -1,19 +1,66 @@
-use crate::{Connection, GameState};
+use crate::{
+    game::id::EntityId,
+    network::admin::{Kicked, PlayerInfo, TickTime},
+    Connection, GameState,
+};
+use diatom_protocol::WelcomePacket;
+use tokio::sync::oneshot;
 
 pub enum GameEvent {
-    Join(Connection),
-    Quit(u16),
-    Input(u16, Input),
+    /// A client finished its handshake. The game replies with the id the connection ended up
+    /// with, or drops the reply if it was turned away.
+    Join(Connection, JoinAs, oneshot::Sender<EntityId>),
+    /// A client left, with the player it had and the id its connection was given
+    Quit(EntityId, EntityId),
+    Kick(EntityId, String),
+    Ack(EntityId, u32),
+    /// Round-trip time in milliseconds measured from a pong
+    Pong(EntityId, u32),
+    Input(EntityId, Input),
+    /// Stop the game and close every connection with the reason
+    Shutdown(String),
+    /// The room's players, for the admin API
+    Players(oneshot::Sender<Vec<PlayerInfo>>),
+    /// Kick a player if it's in this room, replying with its address and resume token. The
+    /// reply is dropped if it's not in this room.
+    AdminKick(EntityId, String, oneshot::Sender<Kicked>),
+    /// Text shown to every player of the room
+    Notice(String),
+    /// A message said by a player, already cleaned up
+    Chat(EntityId, String),
+    TickTime(oneshot::Sender<TickTime>),
+}
+
+/// How a client joins a room
+pub enum JoinAs {
+    /// With the resume token it sent if any, and its cleaned up nickname
+    Player { resume: Option<String>, name: String },
+    /// Without an entity, watching the game
+    Spectator,
 }
 
 pub enum Input {
     Keys(u8, bool),
     Mouse(f64),
     MouseClick(bool),
+    /// Entity a spectator's camera follows
+    Follow(EntityId),
+    /// Where a spectator's free camera is
+    Camera(f64, f64),
 }
 
 pub enum BroadcastEvent {
-    Join(Connection),
-    Quit(u16),
+    Join(Connection, WelcomePacket),
+    Quit(EntityId),
+    Kick(EntityId, String),
+    Ack(EntityId, u32),
+    Pong(EntityId, u32),
     SendState(GameState),
+    Shutdown(String),
+    /// Players with their position, to be completed with what only the connections know
+    Players(Vec<PlayerInfo>, oneshot::Sender<Vec<PlayerInfo>>),
+    /// What the game knows about a kicked player, to be completed with its address
+    AdminKick(EntityId, String, Kicked, oneshot::Sender<Kicked>),
+    Notice(String),
+    Chat(EntityId, String),
 }
\ No newline at end of file
@@ -0,0 +1,255 @@
+use diatom_protocol::ClientPacket;
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
+/// Largest packet a client may send, in bytes
+pub const MAX_FRAME_SIZE: usize = 1024;
+
+/// Frames past this size are refused by the socket itself before being read whole
+pub const MAX_SOCKET_FRAME_SIZE: usize = 64 * 1024;
+
+/// Dropped packets a client is forgiven, see [`InputLimiter`]
+const STRIKES: f64 = 100.0;
+
+/// Strikes given back per second
+const STRIKE_REFILL: f64 = 10.0;
+
+/// ## The kinds of client packets, each rate limited on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputKind {
+    Key,
+    Mouse,
+    MouseClick,
+    Ack,
+    Pong,
+    /// Where a spectator looks
+    Camera,
+    Chat,
+    /// Packets the server ignores after the handshake, and unknown opcodes
+    Other,
+}
+
+impl InputKind {
+    pub const ALL: [InputKind; 8] = [
+        InputKind::Key,
+        InputKind::Mouse,
+        InputKind::MouseClick,
+        InputKind::Ack,
+        InputKind::Pong,
+        InputKind::Camera,
+        InputKind::Chat,
+        InputKind::Other,
+    ];
+
+    pub fn of(packet: &ClientPacket) -> Self {
+        match packet {
+            ClientPacket::Key(..) => InputKind::Key,
+            ClientPacket::Mouse(_) => InputKind::Mouse,
+            ClientPacket::MouseClick(_) => InputKind::MouseClick,
+            ClientPacket::Ack(_) => InputKind::Ack,
+            ClientPacket::Pong(_) => InputKind::Pong,
+            ClientPacket::Follow(_) | ClientPacket::Camera(..) => InputKind::Camera,
+            ClientPacket::Chat(_) => InputKind::Chat,
+            ClientPacket::Hello(_) => InputKind::Other,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            InputKind::Key => "key",
+            InputKind::Mouse => "mouse",
+            InputKind::MouseClick => "mouse_click",
+            InputKind::Ack => "ack",
+            InputKind::Pong => "pong",
+            InputKind::Camera => "camera",
+            InputKind::Chat => "chat",
+            InputKind::Other => "other",
+        }
+    }
+
+    /// Packets per second and burst size allowed
+    fn limit(self, snapshot_rate: f64) -> (f64, f64) {
+        match self {
+            // Held keys repeat, and there are four of them
+            InputKind::Key => (60.0, 60.0),
+            // Sent once per frame, so high refresh rate screens need room
+            InputKind::Mouse => (500.0, 100.0),
+            InputKind::MouseClick => (30.0, 30.0),
+            InputKind::Ack => (snapshot_rate * 2.0, snapshot_rate.max(10.0)),
+            InputKind::Pong => (2.0, 5.0),
+            // A free camera moves once per frame, like the mouse
+            InputKind::Camera => (500.0, 100.0),
+            // A message a second, after a few in a row
+            InputKind::Chat => (1.0, 3.0),
+            InputKind::Other => (5.0, 10.0),
+        }
+    }
+}
+
+/// Packets dropped by every [`InputLimiter`], by [`InputKind`]
+static DROPPED_INPUTS: [AtomicU64; InputKind::ALL.len()] =
+    [const { AtomicU64::new(0) }; InputKind::ALL.len()];
+
+/// Packets dropped since the server started, by kind name
+pub fn dropped_inputs() -> BTreeMap<&'static str, u64> {
+    InputKind::ALL
+        .iter()
+        .map(|&kind| (kind.name(), DROPPED_INPUTS[kind as usize].load(Ordering::Relaxed)))
+        .collect()
+}
+
+struct TokenBucket {
+    tokens: f64,
+    capacity: f64,
+    refill: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(refill: f64, capacity: f64) -> Self {
+        Self {
+            tokens: capacity,
+            capacity,
+            refill,
+            last: Instant::now(),
+        }
+    }
+
+    /// Take a token if one is left
+    fn take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill).min(self.capacity);
+        self.last = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// What to do with a packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    Drop,
+    /// The client kept going over its limits
+    Disconnect,
+}
+
+/// ## Rate limits of one connection
+///
+/// Each [`InputKind`] has a token bucket. A packet without a token is dropped and costs a strike,
+/// and strikes come back slowly, so only clients that keep going over the limits run out.
+pub struct InputLimiter {
+    buckets: [TokenBucket; InputKind::ALL.len()],
+    strikes: TokenBucket,
+    dropped: u64,
+}
+
+impl InputLimiter {
+    pub fn new(snapshot_rate: f64) -> Self {
+        Self {
+            buckets: InputKind::ALL.map(|kind| {
+                let (refill, capacity) = kind.limit(snapshot_rate);
+                TokenBucket::new(refill, capacity)
+            }),
+            strikes: TokenBucket::new(STRIKE_REFILL, STRIKES),
+            dropped: 0,
+        }
+    }
+
+    pub fn check(&mut self, kind: InputKind) -> Verdict {
+        if self.buckets[kind as usize].take() {
+            return Verdict::Allow;
+        }
+
+        self.dropped += 1;
+        DROPPED_INPUTS[kind as usize].fetch_add(1, Ordering::Relaxed);
+
+        if self.strikes.take() {
+            Verdict::Drop
+        } else {
+            Verdict::Disconnect
+        }
+    }
+
+    /// Packets of this connection dropped so far
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // Each test drops packets of its own kinds, the counters are shared between them
+
+    /// Allowed packets of `kind` in a row, from a fresh limiter
+    fn burst(limiter: &mut InputLimiter, kind: InputKind) -> usize {
+        (0..1000).take_while(|_| limiter.check(kind) == Verdict::Allow).count()
+    }
+
+    #[test]
+    fn bursts_past_capacity_are_dropped() {
+        let mut limiter = InputLimiter::new(30.0);
+        let (_, capacity) = InputKind::Chat.limit(30.0);
+        assert_eq!(burst(&mut limiter, InputKind::Chat), capacity as usize);
+        assert_eq!(limiter.check(InputKind::Chat), Verdict::Drop);
+        // Other kinds have their own buckets
+        assert_eq!(limiter.check(InputKind::MouseClick), Verdict::Allow);
+    }
+
+    #[test]
+    fn tokens_refill_over_time() {
+        let mut limiter = InputLimiter::new(30.0);
+        burst(&mut limiter, InputKind::Key);
+        assert_eq!(limiter.check(InputKind::Key), Verdict::Drop);
+
+        // Half a second at 60 a second
+        let bucket = &mut limiter.buckets[InputKind::Key as usize];
+        bucket.last -= Duration::from_millis(500);
+        let refilled = burst(&mut limiter, InputKind::Key);
+        // A slow test run may refill a little more
+        assert!((30..60).contains(&refilled), "{} refilled", refilled);
+    }
+
+    #[test]
+    fn clients_that_keep_flooding_are_disconnected() {
+        let mut limiter = InputLimiter::new(30.0);
+        // The packet that ended the burst was the first strike
+        burst(&mut limiter, InputKind::Other);
+
+        let drops = (0..1000)
+            .take_while(|_| limiter.check(InputKind::Other) == Verdict::Drop)
+            .count();
+        assert_eq!(drops, STRIKES as usize - 1);
+        // The packet that ended the drops was already turned into a disconnect
+        assert_eq!(limiter.check(InputKind::Other), Verdict::Disconnect);
+        assert_eq!(limiter.dropped(), STRIKES as u64 + 2);
+    }
+
+    #[test]
+    fn dropped_packets_are_counted_by_kind() {
+        let before = dropped_inputs();
+        let mut limiter = InputLimiter::new(30.0);
+        burst(&mut limiter, InputKind::Pong);
+        for _ in 0..3 {
+            limiter.check(InputKind::Pong);
+        }
+
+        let after = dropped_inputs();
+        // The one that ended the burst and the three after it
+        assert_eq!(after["pong"] - before["pong"], 4);
+        assert_eq!(after["camera"], before["camera"]);
+        assert_eq!(limiter.dropped(), 4);
+    }
+}
@@ -1,54 +1,50 @@
 use crate::{
-    game::{entity::EntityState, rect::Rectangle},
-    GameState, Message,
+    game::{entity::EntityState, game::Map, id::EntityId},
+    GameState,
+};
+use diatom_protocol::{
+    quantize::{encode_angle, encode_position, encode_size},
+    EntityPacket, Snapshot,
 };
 
-pub trait WriteMessage {
-    fn encode(&self) -> Message;
-}
-
-pub trait ReadMessage {
-    fn from_vec(vec: Vec<Message>) -> Option<Self>
-    where
-        Self: Sized;
-}
-
-impl WriteMessage for Rectangle {
-    fn encode(&self) -> Message {
-        Message::Array(vec![
-            Message::Float64(self.min_x),
-            Message::Float64(self.min_y),
-            Message::Float64(self.max_x),
-            Message::Float64(self.max_y),
-        ])
+fn entity_packet(state: &EntityState, map: &Map) -> EntityPacket {
+    let (x, y) = state.bounds.get_center();
+    EntityPacket {
+        id: state.id.get(),
+        mockup_id: state.mockup_id,
+        x: encode_position(x, map.width),
+        y: encode_position(y, map.height),
+        size: encode_size(state.bounds.get_width()),
+        angle: encode_angle(state.angle),
+        name: state.name.clone(),
     }
 }
 
-fn encode_angle(angle: f64) -> i16 {
-    ((angle % i16::MAX as f64) / 360. * i16::MAX as f64).round() as i16
-}
-
-impl WriteMessage for EntityState {
-    fn encode(&self) -> Message {
-        Message::Array(vec![
-            Message::Uint16(self.id),
-            Message::Uint16(self.mockup_id),
-            Message::Int16(encode_angle(self.angle)),
-            self.bounds.encode(),
-        ])
+impl From<&GameState> for Snapshot {
+    fn from(state: &GameState) -> Self {
+        Snapshot {
+            tick: state.tick,
+            entities: state
+                .entities
+                .iter()
+                .map(|entity| (entity.id.get(), entity_packet(entity, &state.map)))
+                .collect(),
+            map_width: state.map.width,
+            map_height: state.map.height,
+        }
     }
 }
 
-impl WriteMessage for GameState {
-    fn encode(&self) -> Message {
-        let mut message = vec![];
-        for entity in &self.entities {
-            message.push(entity.encode());
-        }
-        message.push(Message::Array(vec![
-            Message::Float64(self.map.width),
-            Message::Float64(self.map.height),
-        ]));
-        Message::Array(message)
+/// Only the entities of `snapshot` listed in `visible`
+pub fn view_snapshot(snapshot: &Snapshot, visible: &[EntityId]) -> Snapshot {
+    Snapshot {
+        tick: snapshot.tick,
+        entities: visible
+            .iter()
+            .filter_map(|id| snapshot.entities.get_key_value(&id.get()))
+            .map(|(id, entity)| (*id, entity.clone()))
+            .collect(),
+        map_width: snapshot.map_width,
+        map_height: snapshot.map_height,
     }
 }
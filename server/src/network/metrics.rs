@@ -0,0 +1,244 @@
+use crate::network::limits::{dropped_inputs, InputKind};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, LazyLock, Mutex,
+    },
+    time::Duration,
+};
+
+/// Upper bounds of the tick duration buckets, in nanoseconds
+const TICK_BUCKETS: [u64; 9] = [
+    250_000, 500_000, 1_000_000, 2_000_000, 4_000_000, 8_000_000, 16_000_000, 32_000_000,
+    64_000_000,
+];
+
+/// Upper bounds of the snapshot size buckets, in bytes
+const SNAPSHOT_BUCKETS: [u64; 9] = [64, 128, 256, 512, 1024, 2048, 4096, 8192, 16384];
+
+/// Bytes and messages that went through every socket, graphed with `rate()`
+static TRAFFIC: Traffic = Traffic {
+    bytes_sent: AtomicU64::new(0),
+    bytes_received: AtomicU64::new(0),
+    messages_sent: AtomicU64::new(0),
+    messages_received: AtomicU64::new(0),
+};
+
+/// Metrics of every open room, by name
+static ROOMS: LazyLock<Mutex<BTreeMap<String, Arc<RoomMetrics>>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+struct Traffic {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+}
+
+/// Count a frame written to a socket
+pub fn sent(bytes: usize) {
+    TRAFFIC.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    TRAFFIC.messages_sent.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count a frame read from a socket
+pub fn received(bytes: usize) {
+    TRAFFIC.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+    TRAFFIC.messages_received.fetch_add(1, Ordering::Relaxed);
+}
+
+/// ## A histogram with fixed buckets, recorded without locking
+///
+/// Values are whole numbers of some unit, `scale` turns them into the unit they're shown in.
+pub struct Histogram {
+    bounds: &'static [u64],
+    scale: f64,
+    /// Not cumulative, the last one counts values past every bound
+    buckets: Vec<AtomicU64>,
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [u64], scale: f64) -> Self {
+        Self {
+            bounds,
+            scale,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: u64) {
+        let bucket = self.bounds.partition_point(|&bound| bound < value);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut count = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            count += bucket.load(Ordering::Relaxed);
+            let le = match self.bounds.get(i) {
+                Some(&bound) => (bound as f64 * self.scale).to_string(),
+                None => "+Inf".to_string(),
+            };
+            let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, le, count);
+        }
+        let sum = self.sum.load(Ordering::Relaxed) as f64 * self.scale;
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, count);
+    }
+}
+
+/// ## What a room's game thread and broadcast task report about themselves
+pub struct RoomMetrics {
+    pub tick_duration: Histogram,
+    /// Size of each snapshot sent, per client since each one gets its own
+    pub snapshot_size: Histogram,
+    /// Players in the game, disconnected ones waiting to be resumed included
+    pub players: AtomicUsize,
+    pub spectators: AtomicUsize,
+    pub clients: AtomicUsize,
+    entities: Mutex<BTreeMap<&'static str, usize>>,
+    /// Events waiting for the game thread
+    pub game_queue: AtomicUsize,
+    /// Events waiting for the broadcast task
+    pub broadcast_queue: AtomicUsize,
+    /// Frames waiting in the writer queues of every client
+    pub frame_queue: AtomicUsize,
+}
+
+impl RoomMetrics {
+    /// Metrics of a new room, listed until it's unregistered
+    pub fn register(room: &str) -> Arc<Self> {
+        let metrics = Arc::new(Self {
+            tick_duration: Histogram::new(&TICK_BUCKETS, 1e-9),
+            snapshot_size: Histogram::new(&SNAPSHOT_BUCKETS, 1.0),
+            players: AtomicUsize::new(0),
+            spectators: AtomicUsize::new(0),
+            clients: AtomicUsize::new(0),
+            entities: Mutex::new(BTreeMap::new()),
+            game_queue: AtomicUsize::new(0),
+            broadcast_queue: AtomicUsize::new(0),
+            frame_queue: AtomicUsize::new(0),
+        });
+        ROOMS.lock().unwrap().insert(room.to_string(), metrics.clone());
+        metrics
+    }
+
+    /// Stop listing a closed room, unless it was opened again in the meantime
+    pub fn unregister(self: &Arc<Self>, room: &str) {
+        let mut rooms = ROOMS.lock().unwrap();
+        if rooms.get(room).is_some_and(|metrics| Arc::ptr_eq(metrics, self)) {
+            rooms.remove(room);
+        }
+    }
+
+    pub fn tick(&self, duration: Duration) {
+        self.tick_duration.observe(duration.as_nanos() as u64);
+    }
+
+    pub fn set_entities(&self, counts: BTreeMap<&'static str, usize>) {
+        *self.entities.lock().unwrap() = counts;
+    }
+}
+
+/// Name, help and value of a gauge every room has
+type Gauge = (&'static str, &'static str, fn(&RoomMetrics) -> usize);
+
+/// Write the help and type lines of a metric
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Every metric in the Prometheus text format, served from `/metrics`
+pub fn render() -> String {
+    let rooms = ROOMS.lock().unwrap().clone();
+    let mut out = String::new();
+
+    header(&mut out, "diatom_rooms", "gauge", "Open rooms");
+    let _ = writeln!(out, "diatom_rooms {}", rooms.len());
+
+    let gauges: [Gauge; 6] = [
+        ("diatom_players", "Players in the room, including the ones waiting to be resumed", |room| {
+            room.players.load(Ordering::Relaxed)
+        }),
+        ("diatom_spectators", "Spectators watching the room", |room| {
+            room.spectators.load(Ordering::Relaxed)
+        }),
+        ("diatom_connected_clients", "Clients connected to the room", |room| {
+            room.clients.load(Ordering::Relaxed)
+        }),
+        ("diatom_game_queue_depth", "Events waiting for the game thread", |room| {
+            room.game_queue.load(Ordering::Relaxed)
+        }),
+        ("diatom_broadcast_queue_depth", "Events waiting for the broadcast task", |room| {
+            room.broadcast_queue.load(Ordering::Relaxed)
+        }),
+        ("diatom_frame_queue_depth", "Frames waiting in the writer queues of the room's clients", |room| {
+            room.frame_queue.load(Ordering::Relaxed)
+        }),
+    ];
+    for (name, help, value) in gauges {
+        header(&mut out, name, "gauge", help);
+        for (room, metrics) in &rooms {
+            let _ = writeln!(out, "{}{{room=\"{}\"}} {}", name, room, value(metrics));
+        }
+    }
+
+    header(&mut out, "diatom_entities", "gauge", "Entities in the room by kind");
+    for (room, metrics) in &rooms {
+        for (kind, count) in metrics.entities.lock().unwrap().iter() {
+            let _ = writeln!(out, "diatom_entities{{room=\"{}\",kind=\"{}\"}} {}", room, kind, count);
+        }
+    }
+
+    header(
+        &mut out,
+        "diatom_tick_duration_seconds",
+        "histogram",
+        "Time spent on one game update, snapshot included",
+    );
+    for (room, metrics) in &rooms {
+        let labels = format!("room=\"{}\"", room);
+        metrics.tick_duration.render(&mut out, "diatom_tick_duration_seconds", &labels);
+    }
+
+    header(&mut out, "diatom_snapshot_size_bytes", "histogram", "Size of each snapshot sent to a client");
+    for (room, metrics) in &rooms {
+        let labels = format!("room=\"{}\"", room);
+        metrics.snapshot_size.render(&mut out, "diatom_snapshot_size_bytes", &labels);
+    }
+
+    let counters = [
+        ("diatom_sent_bytes_total", "Bytes written to every socket", &TRAFFIC.bytes_sent),
+        ("diatom_received_bytes_total", "Bytes read from every socket", &TRAFFIC.bytes_received),
+        ("diatom_sent_messages_total", "Frames written to every socket", &TRAFFIC.messages_sent),
+        ("diatom_received_messages_total", "Frames read from every socket", &TRAFFIC.messages_received),
+    ];
+    for (name, help, counter) in counters {
+        header(&mut out, name, "counter", help);
+        let _ = writeln!(out, "{} {}", name, counter.load(Ordering::Relaxed));
+    }
+
+    header(
+        &mut out,
+        "diatom_dropped_inputs_total",
+        "counter",
+        "Client packets dropped by the rate limits",
+    );
+    let dropped = dropped_inputs();
+    for kind in InputKind::ALL {
+        let _ = writeln!(
+            out,
+            "diatom_dropped_inputs_total{{kind=\"{}\"}} {}",
+            kind.name(),
+            dropped.get(kind.name()).copied().unwrap_or(0)
+        );
+    }
+
+    out
+}
@@ -1,4 +1,12 @@
+pub mod access;
+pub mod admin;
+pub mod chat;
 pub mod events;
+pub mod limits;
 pub mod messages;
-pub mod protocol;
+pub mod metrics;
+pub mod replay;
+pub mod rooms;
+pub mod sessions;
 pub mod server;
+pub mod writer;
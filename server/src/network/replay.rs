@@ -0,0 +1,570 @@
+use crate::{
+    config::{ConfigError, ServerConfig},
+    game::id::EntityId,
+    network::{
+        access::ConnectionSlot,
+        server::{receive_hello, version_mismatch},
+        writer::{spawn_writer, Outbox, GOING_AWAY, NORMAL_CLOSURE, POLICY_VIOLATION},
+    },
+};
+use diatom_protocol::{
+    from_message, to_bytes, ClientPacket, DeltaPacket, Message, Mockup, ServerPacket, Snapshot,
+    WelcomePacket, PROTOCOL_VERSION, SNAPSHOT_HISTORY,
+};
+use futures_util::{stream::SplitStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashSet, VecDeque},
+    fs::{self, File},
+    future::pending,
+    io::{BufWriter, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::watch,
+    task::spawn_blocking,
+    time::{sleep_until, Instant},
+};
+use tokio_util::task::TaskTracker;
+use tracing::{field, info, instrument, warn, Span};
+use warp::filters::ws::WebSocket;
+
+/// First field of every replay file
+const MAGIC: &str = "diatom-replay";
+
+/// Bumped whenever the replays recorded before can't be read anymore
+const REPLAY_VERSION: u16 = 1;
+
+/// ## First record of a replay file
+/// ### [magic, replay version, protocol version, room, unix time it started at, ticks per second, map width, map height, [mockup, ...]]
+///
+/// Entities are stored as the protocol lays them out, so a replay only plays on a server
+/// speaking the protocol it was recorded with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    magic: String,
+    version: u16,
+    protocol: u16,
+    pub room: String,
+    pub started: u64,
+    pub tick_rate: f64,
+    pub map_width: f64,
+    pub map_height: f64,
+    pub mockups: Vec<Mockup>,
+}
+
+impl ReplayHeader {
+    /// The header of a room starting now, with the config its game runs with
+    pub fn new(room: &str, config: &ServerConfig, mockups: &[Mockup]) -> Self {
+        Self {
+            magic: MAGIC.to_string(),
+            version: REPLAY_VERSION,
+            protocol: PROTOCOL_VERSION,
+            room: room.to_string(),
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+            tick_rate: config.tick_rate,
+            map_width: config.map_width,
+            map_height: config.map_height,
+            mockups: mockups.to_vec(),
+        }
+    }
+}
+
+/// ## What happened in a recorded room
+/// ### [tick, [kind, ...]]
+///
+/// Every record after the header is a tick followed by one of these. Joins and quits get the
+/// tick of the snapshot recorded before them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplayEvent {
+    /// Every entity of the room, diffed against the snapshot recorded before it
+    Snapshot(DeltaPacket),
+    /// A player connected, or came back with its resume token
+    Join(u32),
+    /// A player's connection closed, its entity may stay around for it to come back
+    Quit(u32),
+}
+
+enum Recorded {
+    Snapshot(Snapshot),
+    Join(EntityId),
+    Quit(EntityId),
+}
+
+/// ## Writes what a room broadcasts to a replay file
+///
+/// Records are encoded and written from their own thread, so a slow disk never holds up the
+/// broadcast task.
+pub struct Recorder {
+    sender: mpsc::Sender<Recorded>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Recorder {
+    /// Start recording a room to a new file of `dir`, `None` if the file can't be created
+    pub fn create(dir: &Path, header: ReplayHeader) -> Option<Self> {
+        let path = dir.join(format!("{}-{}.replay", header.room, header.started));
+        // Never overwrites, a room reopened within the same second isn't recorded again
+        let created = fs::create_dir_all(dir)
+            .and_then(|_| File::create_new(&path))
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                writer.write_all(&to_bytes(&header).expect("replay headers always serialize"))?;
+                Ok(writer)
+            });
+        let writer = match created {
+            Ok(writer) => writer,
+            Err(e) => {
+                warn!(path = %path.display(), "could not record the room: {}", e);
+                return None;
+            }
+        };
+
+        info!(path = %path.display(), "recording");
+        let (sender, receiver) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name(format!("record-{}", header.room))
+            .spawn(move || write_records(writer, receiver, path))
+            .expect("could not spawn a recording thread");
+        Some(Self { sender, thread })
+    }
+
+    pub fn snapshot(&self, snapshot: Snapshot) {
+        let _ = self.sender.send(Recorded::Snapshot(snapshot));
+    }
+
+    pub fn join(&self, id: EntityId) {
+        let _ = self.sender.send(Recorded::Join(id));
+    }
+
+    /// Spectators and clients that never joined aren't recorded
+    pub fn quit(&self, id: EntityId) {
+        let _ = self.sender.send(Recorded::Quit(id));
+    }
+
+    /// Write what's left and close the file
+    pub async fn finish(self) {
+        drop(self.sender);
+        let _ = spawn_blocking(move || self.thread.join()).await;
+    }
+}
+
+/// Write records until every [`Recorder`] sender is gone
+fn write_records(mut writer: BufWriter<File>, receiver: mpsc::Receiver<Recorded>, path: PathBuf) {
+    let mut last: Option<Snapshot> = None;
+    let mut players = HashSet::new();
+
+    for recorded in receiver {
+        let event = match recorded {
+            Recorded::Snapshot(snapshot) => {
+                let delta = snapshot.diff(last.as_ref());
+                last = Some(snapshot);
+                ReplayEvent::Snapshot(delta)
+            }
+            Recorded::Join(id) => {
+                players.insert(id);
+                ReplayEvent::Join(id.get())
+            }
+            Recorded::Quit(id) => {
+                if !players.remove(&id) {
+                    continue;
+                }
+                ReplayEvent::Quit(id.get())
+            }
+        };
+
+        let tick = last.as_ref().map_or(0, |snapshot| snapshot.tick);
+        let data = to_bytes(&(tick, event)).expect("replay records always serialize");
+        if let Err(e) = writer.write_all(&data) {
+            warn!(path = %path.display(), "could not write the replay, recording stopped: {}", e);
+            return;
+        }
+    }
+
+    match writer.flush() {
+        Ok(()) => info!(path = %path.display(), "replay saved"),
+        Err(e) => warn!(path = %path.display(), "could not write the replay: {}", e),
+    }
+}
+
+/// ## A recorded room, played from the start to every client that connects
+pub struct Replay {
+    pub header: ReplayHeader,
+    records: Vec<(u32, ReplayEvent)>,
+}
+
+impl Replay {
+    /// Read a replay file
+    ///
+    /// A file cut short, like when the server crashed while recording, plays up to where it
+    /// stops.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let data = fs::read(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        let invalid = |reason: String| {
+            ConfigError::Invalid("replay", format!("{} {}", path.display(), reason))
+        };
+
+        let (message, mut offset) = Message::decode(&data)
+            .map_err(|e| invalid(format!("is not a replay: {}", e)))?;
+        let header = from_message::<ReplayHeader>(message)
+            .ok()
+            .filter(|header| header.magic == MAGIC)
+            .ok_or_else(|| invalid("is not a replay".to_string()))?;
+        if header.version != REPLAY_VERSION {
+            return Err(invalid(format!(
+                "is a version {} replay, this server reads version {}",
+                header.version, REPLAY_VERSION
+            )));
+        }
+        if header.protocol != PROTOCOL_VERSION {
+            return Err(invalid(format!(
+                "was recorded with protocol version {}, this server speaks version {}",
+                header.protocol, PROTOCOL_VERSION
+            )));
+        }
+
+        let mut records = Vec::new();
+        while offset < data.len() {
+            let record = Message::decode(&data[offset..])
+                .map_err(|e| e.to_string())
+                .and_then(|(message, length)| {
+                    offset += length;
+                    from_message(message).map_err(|e| e.to_string())
+                });
+            match record {
+                Ok(record) => records.push(record),
+                Err(e) => {
+                    warn!(path = %path.display(), records = records.len(), "replay cut short: {}", e);
+                    break;
+                }
+            }
+        }
+
+        info!(room = %header.room, records = records.len(), "replay loaded");
+        Ok(Self { header, records })
+    }
+}
+
+/// ## Someone watching a replay
+///
+/// Viewers get the whole map, so their camera moves without telling the server.
+struct Viewer {
+    outbox: Outbox,
+    acked_tick: Option<u32>,
+    /// What this viewer was sent, the newest one is where the replay is at
+    history: VecDeque<Snapshot>,
+    /// Players that joined since the last snapshot, announced once it shows their name
+    joined: Vec<u32>,
+}
+
+impl Viewer {
+    fn ack(&mut self, tick: u32) {
+        if self.acked_tick.is_none_or(|acked| tick > acked) {
+            self.acked_tick = Some(tick);
+        }
+    }
+
+    /// Send what happened to the viewer, `false` if the replay is damaged
+    fn play(&mut self, event: &ReplayEvent) -> bool {
+        match event {
+            ReplayEvent::Snapshot(delta) => {
+                let Some(world) = Snapshot::apply(self.history.back(), delta.clone()) else {
+                    return false;
+                };
+                // Viewers that acked a snapshot we no longer have get everything again
+                let base = self
+                    .acked_tick
+                    .and_then(|tick| self.history.iter().find(|s| s.tick == tick));
+                let data = ServerPacket::Delta(world.diff(base)).encode();
+                self.outbox.snapshot(data.into());
+
+                if self.history.len() == SNAPSHOT_HISTORY {
+                    self.history.pop_front();
+                }
+                self.history.push_back(world);
+
+                for id in std::mem::take(&mut self.joined) {
+                    self.notice(format!("{} joined", self.name(id)));
+                }
+            }
+            ReplayEvent::Join(id) => self.joined.push(*id),
+            ReplayEvent::Quit(id) => self.notice(format!("{} left", self.name(*id))),
+        }
+        true
+    }
+
+    /// Nickname of a player in the replay so far
+    fn name(&self, id: u32) -> String {
+        self.history
+            .back()
+            .and_then(|world| world.entities.get(&id))
+            .and_then(|entity| entity.name.clone())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "A player".to_string())
+    }
+
+    fn notice(&self, text: String) {
+        self.outbox.packet(ServerPacket::Notice(text).encode().into());
+    }
+}
+
+/// ## Everyone watching the replay, so they can be told when the server stops
+#[derive(Default)]
+pub struct Viewers {
+    /// The close reason once the server is stopping
+    stopping: watch::Sender<Option<String>>,
+    tasks: TaskTracker,
+}
+
+impl Viewers {
+    /// Play the replay to a client from its own task
+    ///
+    /// The connection counts toward its address's limit until the task ends.
+    pub fn spawn(
+        &self,
+        replay: Arc<Replay>,
+        speed: f64,
+        addr: Option<SocketAddr>,
+        slot: Option<ConnectionSlot>,
+        ws_stream: WebSocket,
+    ) {
+        let stopping = self.stopping.subscribe();
+        self.tasks.spawn(play(replay, speed, addr, slot, ws_stream, stopping));
+    }
+
+    /// Disconnect every viewer, telling them why, and wait for their sockets to close
+    pub async fn shutdown(&self, reason: &str) {
+        self.stopping.send_replace(Some(reason.to_string()));
+        self.tasks.close();
+        self.tasks.wait().await;
+    }
+}
+
+/// Play a replay to a client until the end, until it leaves or until the server stops
+#[instrument(name = "replay", skip_all, fields(ip = field::Empty))]
+async fn play(
+    replay: Arc<Replay>,
+    speed: f64,
+    addr: Option<SocketAddr>,
+    _slot: Option<ConnectionSlot>,
+    ws_stream: WebSocket,
+    mut stopping: watch::Receiver<Option<String>>,
+) {
+    let (ws_sender, ws_receiver) = ws_stream.split();
+    let (outbox, writer) = spawn_writer(ws_sender);
+
+    if let Some(addr) = addr {
+        Span::current().record("ip", field::display(addr.ip()));
+    }
+
+    let close = tokio::select! {
+        reason = stopped(&mut stopping) => Some((GOING_AWAY, reason)),
+        close = playback(&replay, speed, outbox.clone(), ws_receiver) => close,
+    };
+    if let Some((code, reason)) = close {
+        outbox.close(code, reason);
+    }
+    drop(outbox);
+    let _ = writer.await;
+}
+
+/// The close reason, once the server is stopping
+async fn stopped(stopping: &mut watch::Receiver<Option<String>>) -> String {
+    let reason = stopping.wait_for(Option::is_some).await.ok().and_then(|reason| reason.clone());
+    match reason {
+        Some(reason) => reason,
+        // The server can't stop anymore
+        None => pending().await,
+    }
+}
+
+/// Play a replay to a client from its handshake on
+///
+/// Records are sent as they were recorded, `speed` times faster. Acks are the only packets
+/// read from the client. Returns how to close the connection, `None` if the client left.
+async fn playback(
+    replay: &Replay,
+    speed: f64,
+    outbox: Outbox,
+    mut ws_receiver: SplitStream<WebSocket>,
+) -> Option<(u16, String)> {
+    let reason = match receive_hello(&mut ws_receiver).await {
+        Ok(hello) => version_mismatch(hello.version),
+        Err(reason) => Some(reason),
+    };
+    if let Some(reason) = reason {
+        info!(reason, "handshake failed");
+        return Some((POLICY_VIOLATION, reason.to_string()));
+    }
+    info!("watching");
+
+    let header = &replay.header;
+    let welcome = WelcomePacket {
+        version: PROTOCOL_VERSION,
+        // No recorded entity has it
+        id: EntityId::NONE.get(),
+        tick_rate: header.tick_rate,
+        map_width: header.map_width,
+        map_height: header.map_height,
+        token: String::new(),
+        spectator: true,
+    };
+    outbox.packet(ServerPacket::Welcome(welcome).encode().into());
+
+    let mut viewer = Viewer {
+        outbox,
+        acked_tick: None,
+        history: VecDeque::with_capacity(SNAPSHOT_HISTORY),
+        joined: Vec::new(),
+    };
+    let start = Instant::now();
+    let first_tick = replay.records.first().map_or(0, |(tick, _)| *tick);
+
+    for (tick, event) in &replay.records {
+        let ticks = tick.saturating_sub(first_tick) as f64;
+        let due = start + Duration::from_secs_f64(ticks / header.tick_rate / speed);
+        loop {
+            let msg = tokio::select! {
+                _ = sleep_until(due) => break,
+                msg = ws_receiver.next() => msg,
+            };
+            match msg {
+                Some(Ok(msg)) if !msg.is_close() => {
+                    if let Ok(ClientPacket::Ack(tick)) = ClientPacket::decode(msg.as_bytes()) {
+                        viewer.ack(tick);
+                    }
+                }
+                _ => {
+                    info!("stopped watching");
+                    return None;
+                }
+            }
+        }
+
+        if !viewer.play(event) {
+            warn!(tick, "replay damaged, a snapshot doesn't follow the one before it");
+            return Some((POLICY_VIOLATION, "Replay damaged".to_string()));
+        }
+    }
+
+    info!("replay ended");
+    Some((NORMAL_CLOSURE, "Replay ended".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diatom_protocol::EntityPacket;
+    use std::{collections::BTreeMap, env, process};
+
+    fn entity(id: u32, x: i16) -> EntityPacket {
+        EntityPacket {
+            id,
+            mockup_id: 0,
+            x,
+            y: 0,
+            size: 1040,
+            angle: 0,
+            name: Some("tank".to_string()),
+        }
+    }
+
+    fn snapshot(tick: u32, entities: Vec<EntityPacket>) -> Snapshot {
+        Snapshot {
+            tick,
+            entities: entities.into_iter().map(|e| (e.id, e)).collect::<BTreeMap<_, _>>(),
+            map_width: 100.0,
+            map_height: 100.0,
+        }
+    }
+
+    /// Record a short game to a folder of its own, returns the replay file
+    async fn record(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("diatom-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let header = ReplayHeader::new("test", &ServerConfig::default(), &[]);
+        let recorder = Recorder::create(&dir, header).unwrap();
+
+        recorder.snapshot(snapshot(1, vec![entity(1, 0)]));
+        recorder.join(EntityId::from(1));
+        recorder.snapshot(snapshot(2, vec![entity(1, 5), entity(2, 9)]));
+        recorder.quit(EntityId::from(1));
+        // Never joined, like a spectator
+        recorder.quit(EntityId::from(7));
+        recorder.snapshot(snapshot(3, vec![entity(2, 9)]));
+        recorder.finish().await;
+
+        let mut files = fs::read_dir(&dir).unwrap();
+        let path = files.next().unwrap().unwrap().path();
+        assert!(files.next().is_none());
+        path
+    }
+
+    /// The records of a replay with the snapshots applied, and where each one is at
+    fn played(replay: &Replay) -> Vec<(u32, String)> {
+        let mut world: Option<Snapshot> = None;
+        replay
+            .records
+            .iter()
+            .map(|(tick, event)| {
+                let event = match event {
+                    ReplayEvent::Snapshot(delta) => {
+                        world = Some(Snapshot::apply(world.as_ref(), delta.clone()).unwrap());
+                        format!("snapshot {:?}", world.as_ref().unwrap().entities.keys())
+                    }
+                    ReplayEvent::Join(id) => format!("join {}", id),
+                    ReplayEvent::Quit(id) => format!("quit {}", id),
+                };
+                (*tick, event)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn recorded_rooms_load_back() {
+        let path = record("replay").await;
+        let replay = Replay::load(&path).unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        assert_eq!(replay.header.room, "test");
+        assert_eq!(replay.header.tick_rate, ServerConfig::default().tick_rate);
+        let expected = [
+            (1, "snapshot [1]"),
+            (1, "join 1"),
+            (2, "snapshot [1, 2]"),
+            (2, "quit 1"),
+            (3, "snapshot [2]"),
+        ];
+        let expected: Vec<_> = expected.iter().map(|&(tick, e)| (tick, e.to_string())).collect();
+        assert_eq!(played(&replay), expected);
+
+        // The last snapshot is the room as it was recorded
+        let mut world = None;
+        for (_, event) in &replay.records {
+            if let ReplayEvent::Snapshot(delta) = event {
+                world = Snapshot::apply(world.as_ref(), delta.clone());
+            }
+        }
+        assert_eq!(world, Some(snapshot(3, vec![entity(2, 9)])));
+    }
+
+    #[tokio::test]
+    async fn replays_cut_short_load_up_to_the_cut() {
+        let path = record("replay-cut").await;
+        let data = fs::read(&path).unwrap();
+        // Into the last record
+        fs::write(&path, &data[..data.len() - 2]).unwrap();
+        let replay = Replay::load(&path).unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        let played = played(&replay);
+        assert_eq!(played.len(), 4);
+        assert_eq!(played[3], (2, "quit 1".to_string()));
+    }
+}
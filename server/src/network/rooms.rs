@@ -0,0 +1,268 @@
+use crate::{
+    config::{GameMode, RoomConfig, ServerConfig},
+    game::id::{EntityId, IdAllocator},
+    network::{
+        events::{BroadcastEvent, GameEvent, JoinAs},
+        metrics::RoomMetrics,
+        replay::{Recorder, ReplayHeader},
+        server::{broadcast, run},
+        writer::POLICY_VIOLATION,
+    },
+    Connection,
+};
+use diatom_protocol::Mockup;
+use futures_util::future::join_all;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::Ordering,
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{
+        mpsc::{unbounded_channel, UnboundedSender},
+        oneshot,
+    },
+    task::{spawn_blocking, JoinHandle},
+    time::interval,
+};
+use tracing::{info, info_span, Instrument};
+
+/// Time between two looks for empty rooms to close
+const CLOSE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Close reason clients still in a room see when it closes
+const CLOSE_REASON: &str = "Room closed";
+
+struct Room {
+    config: RoomConfig,
+    /// The server's config with the room's settings on top
+    game_config: ServerConfig,
+    sender: UnboundedSender<GameEvent>,
+    metrics: Arc<RoomMetrics>,
+    /// Rooms from the config file stay open when empty
+    persistent: bool,
+    empty_since: Option<Instant>,
+    game_thread: thread::JoinHandle<()>,
+    broadcast_task: JoinHandle<()>,
+}
+
+impl Room {
+    fn open(
+        config: RoomConfig,
+        server: &ServerConfig,
+        ids: Arc<IdAllocator>,
+        mockups: &[Mockup],
+        persistent: bool,
+    ) -> Self {
+        let game_config = server.for_room(&config);
+
+        let metrics = RoomMetrics::register(&config.name);
+        let recorder = server.record_dir.as_deref().and_then(|dir| {
+            Recorder::create(dir, ReplayHeader::new(&config.name, &game_config, mockups))
+        });
+
+        let (broadcast_sender, broadcast_receiver) = unbounded_channel::<BroadcastEvent>();
+        // Rooms opened by a client would be inside its connection's span otherwise
+        let span = info_span!(parent: None, "broadcast", room = %config.name);
+        let broadcast_task =
+            tokio::spawn(broadcast(broadcast_receiver, metrics.clone(), recorder).instrument(span));
+
+        let (sender, receiver) = unbounded_channel::<GameEvent>();
+        let thread_config = game_config.clone();
+        let thread_metrics = metrics.clone();
+        let thread_name = config.name.clone();
+        let game_thread = thread::Builder::new()
+            .name(format!("room-{}", config.name))
+            .spawn(move || {
+                let _span = info_span!("game", room = %thread_name).entered();
+                run(thread_config, ids, thread_metrics, broadcast_sender, receiver)
+            })
+            .expect("could not spawn a game thread");
+
+        info!(room = %config.name, "room opened");
+        Self {
+            config,
+            game_config,
+            sender,
+            metrics,
+            persistent,
+            empty_since: None,
+            game_thread,
+            broadcast_task,
+        }
+    }
+
+    /// Stop the game and wait for every client in the room to be disconnected
+    async fn close(self, reason: &str) {
+        self.metrics.unregister(&self.config.name);
+        let _ = self.sender.send(GameEvent::Shutdown(reason.to_string()));
+        let _ = self.broadcast_task.await;
+        let _ = spawn_blocking(move || self.game_thread.join()).await;
+    }
+}
+
+/// What `/rooms.json` lists about a room
+#[derive(Serialize)]
+pub struct RoomInfo {
+    name: String,
+    mode: GameMode,
+    players: usize,
+    max_players: usize,
+    spectators: usize,
+    map_width: f64,
+    map_height: f64,
+}
+
+/// ## Every room of the server, each one running its own game
+///
+/// The rooms from the config are opened right away and stay open. Joining any other room
+/// opens it, as long as there are less than `max_rooms`, and it's closed once it has been
+/// empty for `empty_room_timeout`.
+pub struct Rooms {
+    pub config: ServerConfig,
+    /// Ids are unique across rooms, so one allocator serves all of them
+    pub ids: Arc<IdAllocator>,
+    /// Written at the start of each room's replay, when rooms are recorded
+    mockups: Vec<Mockup>,
+    rooms: Mutex<HashMap<String, Room>>,
+}
+
+impl Rooms {
+    /// Open the rooms from the config, must be called from within the runtime
+    pub fn new(config: ServerConfig, ids: Arc<IdAllocator>, mockups: Vec<Mockup>) -> Self {
+        let rooms = config
+            .rooms
+            .iter()
+            .map(|room| {
+                let room = Room::open(room.clone(), &config, ids.clone(), &mockups, true);
+                (room.config.name.clone(), room)
+            })
+            .collect();
+
+        Self {
+            config,
+            ids,
+            mockups,
+            rooms: Mutex::new(rooms),
+        }
+    }
+
+    /// The room clients join when they don't pick one
+    pub fn default_room(&self) -> &str {
+        &self.config.rooms[0].name
+    }
+
+    /// Send a connection that finished its handshake to a room, opening the room if needed
+    ///
+    /// Returns the room's game sender, or turns the connection away if the room can't be opened.
+    pub fn join(
+        &self,
+        name: &str,
+        conn: Connection,
+        join: JoinAs,
+        reply: oneshot::Sender<EntityId>,
+    ) -> Option<UnboundedSender<GameEvent>> {
+        let mut rooms = self.rooms.lock().unwrap();
+
+        if !rooms.contains_key(name) {
+            if rooms.len() >= self.config.max_rooms {
+                info!(client = conn.id.get(), room = name, "can't open a room, too many are open");
+                conn.outbox.close(POLICY_VIOLATION, "Too many rooms".to_string());
+                self.ids.release(conn.id);
+                return None;
+            }
+            let room = Room::open(
+                RoomConfig::named(name),
+                &self.config,
+                self.ids.clone(),
+                &self.mockups,
+                false,
+            );
+            rooms.insert(name.to_string(), room);
+        }
+
+        // Sent while holding the lock, so the room can't be closed before the join reaches it
+        let room = rooms.get_mut(name).unwrap();
+        room.empty_since = None;
+        let _ = room.sender.send(GameEvent::Join(conn, join, reply));
+        Some(room.sender.clone())
+    }
+
+    /// The game sender of every open room
+    pub fn senders(&self) -> Vec<(String, UnboundedSender<GameEvent>)> {
+        let rooms = self.rooms.lock().unwrap();
+        rooms
+            .iter()
+            .map(|(name, room)| (name.clone(), room.sender.clone()))
+            .collect()
+    }
+
+    pub fn list(&self) -> Vec<RoomInfo> {
+        let rooms = self.rooms.lock().unwrap();
+        let mut list: Vec<RoomInfo> = rooms
+            .values()
+            .map(|room| RoomInfo {
+                name: room.config.name.clone(),
+                mode: room.config.mode,
+                players: room.metrics.players.load(Ordering::Relaxed),
+                max_players: room.game_config.max_players,
+                spectators: room.metrics.spectators.load(Ordering::Relaxed),
+                map_width: room.game_config.map_width,
+                map_height: room.game_config.map_height,
+            })
+            .collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+
+    /// Close the rooms opened by clients that stayed empty for too long
+    fn close_empty(&self) {
+        let timeout = self.config.empty_room_timeout();
+        let mut rooms = self.rooms.lock().unwrap();
+
+        let mut closing = Vec::new();
+        for (name, room) in rooms.iter_mut() {
+            let clients = room.metrics.players.load(Ordering::Relaxed)
+                + room.metrics.spectators.load(Ordering::Relaxed);
+            if room.persistent || clients > 0 {
+                room.empty_since = None;
+                continue;
+            }
+            // Seen empty twice at least, a room opened by a join isn't closed before the join lands
+            let Some(since) = room.empty_since else {
+                room.empty_since = Some(Instant::now());
+                continue;
+            };
+            if since.elapsed() >= timeout {
+                closing.push(name.clone());
+            }
+        }
+
+        for name in closing {
+            if let Some(room) = rooms.remove(&name) {
+                info!(room = %name, "room closed, nobody was in it");
+                tokio::spawn(room.close(CLOSE_REASON));
+            }
+        }
+    }
+
+    /// Close every room, telling the clients why
+    pub async fn shutdown(&self, reason: &str) {
+        let rooms: Vec<Room> = self.rooms.lock().unwrap().drain().map(|(_, room)| room).collect();
+        join_all(rooms.into_iter().map(|room| room.close(reason))).await;
+    }
+}
+
+/// Close empty rooms as they time out, runs until the server stops
+pub async fn close_empty_rooms(rooms: Arc<Rooms>) {
+    let mut checks = interval(CLOSE_CHECK_INTERVAL);
+    loop {
+        checks.tick().await;
+        rooms.close_empty();
+    }
+}
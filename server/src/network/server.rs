@@ -1,21 +1,96 @@
-use crate::{network::events::*, Connection, Game, Message, WriteMessage};
-use futures_util::{FutureExt, SinkExt, StreamExt};
+use crate::{
+    network::{
+        access::{Bans, ConnectionSlot},
+        events::*,
+        limits::{InputKind, InputLimiter, Verdict, MAX_FRAME_SIZE},
+        metrics::{received, RoomMetrics},
+        replay::Recorder,
+        admin::{Kicked, PlayerInfo, TickTime},
+        chat,
+        messages::view_snapshot,
+        rooms::Rooms,
+        sessions::Sessions,
+        writer::{spawn_writer, GOING_AWAY, POLICY_VIOLATION},
+    },
+    config::ServerConfig,
+    game::{
+        game::Map,
+        id::{EntityId, IdAllocator},
+    },
+    Connection, Game,
+};
+use bytes::Bytes;
+use diatom_protocol::{
+    ChatPacket, ClientPacket, Error as PacketError, HelloPacket, Message, PingPacket, ServerPacket, Snapshot, WelcomePacket,
+    BUILD_HASH, PROTOCOL_VERSION, SNAPSHOT_HISTORY,
+};
+use futures_util::{future::join_all, stream::SplitStream, FutureExt, StreamExt};
 use std::{
     collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::Ordering,
+        Arc, LazyLock,
+    },
     thread,
     time::{Duration, Instant},
 };
 use tokio::{
-    sync::mpsc::{UnboundedReceiver, UnboundedSender},
+    sync::{
+        mpsc::{UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
     task::unconstrained,
+    time::{interval, timeout},
 };
-use warp::filters::ws::{Message as SocketMessage, WebSocket};
+use tracing::{debug, field, info, instrument, warn, Span};
+use warp::filters::ws::WebSocket;
+
+/// How long a client has to send its [`HelloPacket`] after connecting
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time between two [`PingPacket`]s
+const PING_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long a client can stay silent, pongs included, before it's dropped
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Weight of a new round-trip time sample in the smoothed one
+const RTT_SMOOTHING: f64 = 0.125;
+
+/// Weight of a new update duration in the smoothed tick time
+const TICK_TIME_SMOOTHING: f64 = 0.05;
+
+/// When the server started, ping times are counted from it
+static START: LazyLock<Instant> = LazyLock::new(Instant::now);
 
-pub fn run(sender: UnboundedSender<BroadcastEvent>, mut receiver: UnboundedReceiver<GameEvent>) {
-    let mut game = Game::new();
+/// Milliseconds since the server started, wrapping after about 49 days
+fn now_ms() -> u32 {
+    START.elapsed().as_millis() as u32
+}
+
+pub fn run(
+    config: ServerConfig,
+    ids: Arc<IdAllocator>,
+    metrics: Arc<RoomMetrics>,
+    sender: UnboundedSender<BroadcastEvent>,
+    mut receiver: UnboundedReceiver<GameEvent>,
+) {
+    let mut game = Game::new(
+        Map {
+            width: config.map_width,
+            height: config.map_height,
+        },
+        ids,
+    );
+    let tick_interval = config.tick_interval();
+    let ticks_per_snapshot = config.ticks_per_snapshot();
+    let mut sessions = Sessions::new(config.resume_grace());
 
     let mut accum = 0.0;
     let mut dt = 0.0;
+    // Seconds spent on one update, snapshot included
+    let mut tick_time = 0.0;
 
     loop {
         let start_time = Instant::now();
@@ -23,14 +98,84 @@ pub fn run(sender: UnboundedSender<BroadcastEvent>, mut receiver: UnboundedRecei
         while let Some(is_event) = unconstrained(receiver.recv()).now_or_never() {
             if let Some(event) = is_event {
                 match event {
-                    GameEvent::Join(conn) => {
-                        game.add_player(conn.id);
-                        let _ = sender.send(BroadcastEvent::Join(conn));
+                    GameEvent::Join(conn, JoinAs::Spectator, reply) => {
+                        if game.spectator_count() >= config.max_spectators {
+                            info!(client = conn.id.get(), "turned away, too many spectators");
+                            conn.outbox.close(POLICY_VIOLATION, "Too many spectators".to_string());
+                            game.ids.release(conn.id);
+                            continue;
+                        }
+                        info!(client = conn.id.get(), "spectating");
+                        game.add_spectator(conn.id);
+
+                        let welcome = WelcomePacket {
+                            version: PROTOCOL_VERSION,
+                            id: conn.id.get(),
+                            tick_rate: config.tick_rate,
+                            map_width: game.map.width,
+                            map_height: game.map.height,
+                            token: String::new(),
+                            spectator: true,
+                        };
+                        let _ = reply.send(conn.id);
+                        let _ = sender.send(BroadcastEvent::Join(conn, welcome));
                     }
-                    GameEvent::Quit(id) => {
-                        game.remove_entity_at_id(id);
+                    GameEvent::Join(mut conn, JoinAs::Player { resume, name }, reply) => {
+                        let resumed = resume.and_then(|token| sessions.resume(&token, conn.id));
+                        if let Some(id) = resumed {
+                            info!(client = conn.id.get(), player = id.get(), "player resumed");
+                            game.ids.release(conn.id);
+                            conn.id = id;
+                            // Keys held down on a connection it was taken over from
+                            game.clear_input(id);
+                        } else if game.player_count() >= config.max_players {
+                            info!(client = conn.id.get(), "turned away, the room is full");
+                            conn.outbox.close(POLICY_VIOLATION, "Server is full".to_string());
+                            game.ids.release(conn.id);
+                            continue;
+                        } else {
+                            game.add_player(conn.id, name);
+                            sessions.create(conn.id);
+                        }
+
+                        let welcome = WelcomePacket {
+                            version: PROTOCOL_VERSION,
+                            id: conn.id.get(),
+                            tick_rate: config.tick_rate,
+                            map_width: game.map.width,
+                            map_height: game.map.height,
+                            token: sessions.token(conn.id).unwrap_or_default().to_string(),
+                            spectator: false,
+                        };
+                        let _ = reply.send(conn.id);
+                        let _ = sender.send(BroadcastEvent::Join(conn, welcome));
+                    }
+                    GameEvent::Quit(id, connection) => {
+                        // The connection was taken over, the player isn't leaving
+                        if sessions.taken_over(id, connection) {
+                            continue;
+                        }
+                        // Keep the player around for its client to come back
+                        if !game.remove_spectator(id) {
+                            game.clear_input(id);
+                            if !sessions.disconnect(id) {
+                                game.remove_entity_at_id(id);
+                            }
+                        }
                         let _ = sender.send(BroadcastEvent::Quit(id));
                     }
+                    GameEvent::Kick(id, reason) => {
+                        sessions.remove(id);
+                        game.remove_entity_at_id(id);
+                        game.remove_spectator(id);
+                        let _ = sender.send(BroadcastEvent::Kick(id, reason));
+                    }
+                    GameEvent::Ack(id, tick) => {
+                        let _ = sender.send(BroadcastEvent::Ack(id, tick));
+                    }
+                    GameEvent::Pong(id, rtt) => {
+                        let _ = sender.send(BroadcastEvent::Pong(id, rtt));
+                    }
                     GameEvent::Input(id, input) => match input {
                         Input::Keys(key, value) => {
                             game.set_input(id, key, value);
@@ -41,97 +186,471 @@ pub fn run(sender: UnboundedSender<BroadcastEvent>, mut receiver: UnboundedRecei
                         Input::MouseClick(b) => {
                             game.set_mouse_click(id, b);
                         }
+                        Input::Follow(target) => {
+                            game.follow(id, target);
+                        }
+                        Input::Camera(x, y) => {
+                            game.move_camera(id, x, y);
+                        }
                     },
+                    GameEvent::Shutdown(reason) => {
+                        let _ = sender.send(BroadcastEvent::Shutdown(reason));
+                        return;
+                    }
+                    GameEvent::Players(reply) => {
+                        let players = game
+                            .player_positions()
+                            .into_iter()
+                            .map(|(id, name, x, y)| PlayerInfo::new(id, name, x, y))
+                            .chain(
+                                game.spectator_positions()
+                                    .into_iter()
+                                    .map(|(id, x, y)| PlayerInfo::spectator(id, x, y)),
+                            )
+                            .collect();
+                        let _ = sender.send(BroadcastEvent::Players(players, reply));
+                    }
+                    GameEvent::AdminKick(id, reason, reply) => {
+                        if game.has_player(id) {
+                            let kicked = Kicked {
+                                ip: None,
+                                token: sessions.token(id).map(str::to_string),
+                            };
+                            sessions.remove(id);
+                            game.remove_entity_at_id(id);
+                            let _ = sender.send(BroadcastEvent::AdminKick(id, reason, kicked, reply));
+                        } else if game.remove_spectator(id) {
+                            let kicked = Kicked::default();
+                            let _ = sender.send(BroadcastEvent::AdminKick(id, reason, kicked, reply));
+                        }
+                    }
+                    GameEvent::Notice(text) => {
+                        let _ = sender.send(BroadcastEvent::Notice(text));
+                    }
+                    GameEvent::Chat(id, text) => {
+                        // Spectators have no entity to say it
+                        if game.has_player(id) {
+                            let _ = sender.send(BroadcastEvent::Chat(id, text));
+                        }
+                    }
+                    GameEvent::TickTime(reply) => {
+                        let _ = reply.send(TickTime {
+                            tick: game.tick,
+                            tick_time: tick_time * 1000.0,
+                            budget: tick_interval * 1000.0,
+                        });
+                    }
                 }
+            } else {
+                // Every sender is gone, nothing can reach the game anymore
+                return;
             }
         }
 
+        for id in sessions.expired() {
+            debug!(player = id.get(), "not resumed in time, removed");
+            game.remove_entity_at_id(id);
+        }
+        metrics.players.store(game.player_count(), Ordering::Relaxed);
+        metrics.spectators.store(game.spectator_count(), Ordering::Relaxed);
+        metrics.game_queue.store(receiver.len(), Ordering::Relaxed);
+
         accum += dt;
-        while accum >= 0.016 {
-            accum -= 0.016;
+        while accum >= tick_interval {
+            accum -= tick_interval;
 
+            let update_start = Instant::now();
             game.update();
 
-            let _ = sender.send(BroadcastEvent::SendState(game.get_state()));
+            if game.tick.is_multiple_of(ticks_per_snapshot) {
+                let _ = sender.send(BroadcastEvent::SendState(game.get_state()));
+                metrics.set_entities(game.entity_counts());
+            }
+            let elapsed = update_start.elapsed();
+            metrics.tick(elapsed);
+            tick_time += (elapsed.as_secs_f64() - tick_time) * TICK_TIME_SMOOTHING;
         }
 
-        thread::sleep(Duration::from_millis(1000 / 30));
-        dt = start_time.elapsed().as_secs_f32();
-        //println!("MSPT (Milliseconds per tick): {:.3}", dt);
+        // Sleep off the rest of the tick
+        let elapsed = start_time.elapsed().as_secs_f64();
+        if elapsed < tick_interval {
+            thread::sleep(Duration::from_secs_f64(tick_interval - elapsed));
+        }
+        dt = start_time.elapsed().as_secs_f64();
     }
 }
 
-pub async fn broadcast(mut receiver: UnboundedReceiver<BroadcastEvent>) {
-    let mut connections: HashMap<u16, Connection> = HashMap::new();
+/// Send what the game thread tells to the room's connections, and record it if `recorder` is set
+pub async fn broadcast(
+    mut receiver: UnboundedReceiver<BroadcastEvent>,
+    metrics: Arc<RoomMetrics>,
+    recorder: Option<Recorder>,
+) {
+    let mut connections: HashMap<EntityId, Connection> = HashMap::new();
+    let mut pings = interval(PING_INTERVAL);
+
+    loop {
+        let event = tokio::select! {
+            event = receiver.recv() => match event {
+                Some(event) => event,
+                None => return,
+            },
+            _ = pings.tick() => {
+                send_pings(&connections);
+                continue;
+            }
+        };
 
-    while let Some(event) = receiver.recv().await {
         match event {
-            BroadcastEvent::Join(conn) => {
-                connections.insert(conn.id, conn);
+            BroadcastEvent::Join(conn, welcome) => {
+                let id = conn.id;
+                let spectator = welcome.spectator;
+                conn.outbox.packet(ServerPacket::Welcome(welcome).encode().into());
+                if let Some(old) = connections.insert(id, conn) {
+                    // Resumed while the connection it had still looked alive
+                    info!(client = id.get(), "taken over by a new connection");
+                    old.outbox.close(POLICY_VIOLATION, "Resumed somewhere else".to_string());
+                } else if let Some(recorder) = recorder.as_ref().filter(|_| !spectator) {
+                    recorder.join(id);
+                }
             }
 
             BroadcastEvent::Quit(id) => {
+                if let Some(recorder) = &recorder {
+                    recorder.quit(id);
+                }
                 connections.remove(&id);
-                println!("Client {} disconnected", id);
+                info!(client = id.get(), "disconnected");
+            }
+
+            BroadcastEvent::Kick(id, reason) => {
+                info!(client = id.get(), %reason, "kicked");
+                if let Some(recorder) = &recorder {
+                    recorder.quit(id);
+                }
+                if let Some(conn) = connections.remove(&id) {
+                    conn.outbox.close(POLICY_VIOLATION, reason);
+                }
+            }
+
+            BroadcastEvent::Ack(id, tick) => {
+                if let Some(conn) = connections.get_mut(&id) {
+                    if conn.acked_tick.is_none_or(|acked| tick > acked) {
+                        conn.acked_tick = Some(tick);
+                    }
+                }
+            }
+
+            BroadcastEvent::Pong(id, rtt) => {
+                if let Some(conn) = connections.get_mut(&id) {
+                    let rtt = rtt as f64;
+                    conn.rtt = Some(match conn.rtt {
+                        Some(smoothed) => smoothed + (rtt - smoothed) * RTT_SMOOTHING,
+                        None => rtt,
+                    });
+                }
             }
 
             BroadcastEvent::SendState(state) => {
+                metrics.clients.store(connections.len(), Ordering::Relaxed);
+                metrics.broadcast_queue.store(receiver.len(), Ordering::Relaxed);
+                let queued = connections
+                    .values()
+                    .map(|conn| conn.outbox.queued())
+                    .sum();
+                metrics.frame_queue.store(queued, Ordering::Relaxed);
+
+                let snapshot = Snapshot::from(&state);
                 for conn in connections.values_mut() {
-                    let data = state.encode();
-                    let _ = conn.sender.send(SocketMessage::binary(data.encode())).await;
+                    let visible = state.views.get(&conn.id).map_or(&[][..], Vec::as_slice);
+                    let snapshot = view_snapshot(&snapshot, visible);
+
+                    // Clients that acked a snapshot we no longer have get everything again
+                    let base = conn
+                        .acked_tick
+                        .and_then(|tick| conn.history.iter().find(|s| s.tick == tick));
+                    // Deltas differ per client, so each is encoded once for its own connection
+                    let data = ServerPacket::Delta(snapshot.diff(base)).encode();
+                    metrics.snapshot_size.observe(data.len() as u64);
+                    conn.outbox.snapshot(data.into());
+
+                    if conn.history.len() == SNAPSHOT_HISTORY {
+                        conn.history.pop_front();
+                    }
+                    conn.history.push_back(snapshot);
+                }
+
+                if let Some(recorder) = &recorder {
+                    recorder.snapshot(snapshot);
                 }
             }
+
+            BroadcastEvent::Players(mut players, reply) => {
+                for player in &mut players {
+                    if let Some(conn) = connections.get(&player.id()) {
+                        player.connected(conn.rtt, conn.ip);
+                    }
+                }
+                let _ = reply.send(players);
+            }
+
+            BroadcastEvent::AdminKick(id, reason, mut kicked, reply) => {
+                let conn = connections.remove(&id);
+                if let Some(conn) = &conn {
+                    conn.outbox.close(POLICY_VIOLATION, reason);
+                }
+                info!(client = id.get(), "kicked by an admin");
+                if let Some(recorder) = &recorder {
+                    recorder.quit(id);
+                }
+                kicked.ip = conn.and_then(|conn| conn.ip);
+                let _ = reply.send(kicked);
+            }
+
+            BroadcastEvent::Notice(text) => {
+                let data = Bytes::from(ServerPacket::Notice(text).encode());
+                for conn in connections.values() {
+                    conn.outbox.packet(data.clone());
+                }
+            }
+
+            BroadcastEvent::Chat(id, text) => {
+                info!(player = id.get(), %text, "chat");
+                let data = Bytes::from(ServerPacket::Chat(ChatPacket { id: id.get(), text }).encode());
+                for conn in connections.values() {
+                    conn.outbox.packet(data.clone());
+                }
+            }
+
+            BroadcastEvent::Shutdown(reason) => {
+                let writers = connections.into_values().map(|conn| {
+                    conn.outbox.close(GOING_AWAY, reason.clone());
+                    conn.writer
+                });
+                join_all(writers).await;
+                info!("every client was disconnected");
+                if let Some(recorder) = recorder {
+                    recorder.finish().await;
+                }
+                return;
+            }
         }
     }
 }
 
-pub async fn listen(game_sender: UnboundedSender<GameEvent>, ws_stream: WebSocket) {
+/// Ping every client, telling each its own round-trip time
+///
+/// Clients with the same round-trip time share one encoded ping.
+fn send_pings(connections: &HashMap<EntityId, Connection>) {
+    let time = now_ms();
+    let mut pings: HashMap<Option<u16>, Bytes> = HashMap::new();
+    for conn in connections.values() {
+        let rtt = conn.rtt.map(|rtt| rtt.round().min(u16::MAX as f64) as u16);
+        let data = pings
+            .entry(rtt)
+            .or_insert_with(|| ServerPacket::Ping(PingPacket { time, rtt }).encode().into());
+        conn.outbox.packet(data.clone());
+    }
+}
+
+/// Handle a client from its handshake until it leaves
+///
+/// The connection counts toward its address's limit until `_slot` is dropped with it.
+#[instrument(
+    name = "connection",
+    skip_all,
+    fields(%room, ip = field::Empty, id = field::Empty, player = field::Empty)
+)]
+pub async fn listen(
+    rooms: Arc<Rooms>,
+    bans: Arc<Bans>,
+    room: String,
+    addr: Option<SocketAddr>,
+    _slot: Option<ConnectionSlot>,
+    ws_stream: WebSocket,
+) {
     let (ws_sender, mut ws_receiver) = ws_stream.split();
 
-    let mut connection = Connection::new(ws_sender);
+    let (outbox, writer) = spawn_writer(ws_sender);
+
+    let ip = addr.map(|addr| addr.ip());
+    let connection = Connection::new(rooms.ids.allocate(), ip, outbox.clone(), writer);
     let id = connection.id;
-    println!("Client {} connected", id);
-    connection
-        .sender
-        .send(SocketMessage::binary(Message::Uint16(id).encode()))
-        .await
-        .unwrap();
-
-    let _ = game_sender.send(GameEvent::Join(connection));
-
-    while let Some(msg) = ws_receiver.next().await {
-        if let Ok(msg) = msg {
-            if msg.is_binary() {
-                let decoded_message = Message::decode(&msg.into_bytes());
-                if let Message::Array(vec) = decoded_message {
-                    match vec.as_slice() {
-                        [Message::Uint8(upordown), Message::Uint8(key)] => match upordown {
-                            0 => {
-                                let _ =
-                                    game_sender.send(GameEvent::Input(id, Input::Keys(*key, true)));
-                            }
-                            1 => {
-                                let _ = game_sender
-                                    .send(GameEvent::Input(id, Input::Keys(*key, false)));
-                            }
-                            _ => {}
-                        },
-                        [Message::Float64(rad)] => {
-                            let _ = game_sender.send(GameEvent::Input(id, Input::Mouse(*rad)));
-                        }
-                        [Message::Bool(b)] => {
-                            let _ = game_sender.send(GameEvent::Input(id, Input::MouseClick(*b)));
+    let span = Span::current();
+    span.record("id", id.get());
+    if let Some(ip) = ip {
+        span.record("ip", field::display(ip));
+    }
+    info!("connected");
+
+    let mut join = JoinAs::Spectator;
+    let reason = match receive_hello(&mut ws_receiver).await {
+        Ok(hello) if hello.version != PROTOCOL_VERSION => version_mismatch(hello.version),
+        Ok(hello) if hello.resume.as_deref().is_some_and(|token| bans.is_token_banned(token)) => {
+            Some("Banned")
+        }
+        Ok(hello) => {
+            if hello.build != BUILD_HASH {
+                debug!(build = %hello.build, server_build = BUILD_HASH, "client runs another build");
+            }
+            if !hello.spectate {
+                join = JoinAs::Player {
+                    resume: hello.resume,
+                    name: chat::clean_name(&hello.name, &rooms.config.chat_filter),
+                };
+            }
+            None
+        }
+        Err(reason) => Some(reason),
+    };
+    if let Some(reason) = reason {
+        info!(reason, "handshake failed");
+        outbox.close(POLICY_VIOLATION, reason.to_string());
+        rooms.ids.release(id);
+        return;
+    }
+
+    let (reply_sender, reply) = oneshot::channel();
+    let Some(game_sender) = rooms.join(&room, connection, join, reply_sender) else {
+        return;
+    };
+    // A resumed player keeps its old id
+    let Ok(resumed) = reply.await else {
+        return;
+    };
+    if resumed != id {
+        span.record("player", resumed.get());
+    }
+    let (id, connection) = (resumed, id);
+
+    let mut limiter = InputLimiter::new(rooms.config.snapshot_rate);
+
+    loop {
+        let read = tokio::select! {
+            // Kicked, taken over or too slow, the game knows which quits to ignore
+            _ = outbox.closed() => break,
+            read = timeout(IDLE_TIMEOUT, ws_receiver.next()) => read,
+        };
+        let msg = match read {
+            Ok(Some(Ok(msg))) => {
+                received(msg.as_bytes().len());
+                msg
+            }
+            Ok(_) => break,
+            Err(_) => {
+                // Not a kick, a client that lost its network can still resume
+                info!("timed out");
+                outbox.close(POLICY_VIOLATION, "Timed out".to_string());
+                break;
+            }
+        };
+
+        if msg.as_bytes().len() > MAX_FRAME_SIZE {
+            warn!(size = msg.as_bytes().len(), "frame too large");
+            let _ = game_sender.send(GameEvent::Kick(id, "Packet too large".to_string()));
+            return;
+        }
+
+        if msg.is_binary() {
+            let packet = ClientPacket::decode(msg.as_bytes());
+            let kind = packet.as_ref().map_or(InputKind::Other, InputKind::of);
+            match limiter.check(kind) {
+                Verdict::Allow => {}
+                Verdict::Drop => continue,
+                Verdict::Disconnect => {
+                    warn!(dropped = limiter.dropped(), "kept flooding");
+                    let _ = game_sender.send(GameEvent::Kick(id, "Too many packets".to_string()));
+                    return;
+                }
+            }
+
+            let input = match packet {
+                Ok(ClientPacket::Key(key, value)) => Input::Keys(key, value),
+                Ok(ClientPacket::Mouse(rad)) => Input::Mouse(rad),
+                Ok(ClientPacket::MouseClick(b)) => Input::MouseClick(b),
+                Ok(ClientPacket::Follow(target)) => Input::Follow(EntityId::from(target)),
+                Ok(ClientPacket::Camera(x, y)) => Input::Camera(x, y),
+                Ok(ClientPacket::Ack(tick)) => {
+                    let _ = game_sender.send(GameEvent::Ack(id, tick));
+                    continue;
+                }
+                Ok(ClientPacket::Pong(time)) => {
+                    let rtt = now_ms().wrapping_sub(time);
+                    // Anything older than the idle timeout wasn't a ping we sent
+                    if rtt <= IDLE_TIMEOUT.as_millis() as u32 {
+                        let _ = game_sender.send(GameEvent::Pong(id, rtt));
+                    }
+                    continue;
+                }
+                Ok(ClientPacket::Chat(text)) => {
+                    match chat::clean(&text, &rooms.config.chat_filter) {
+                        Some(text) => {
+                            let _ = game_sender.send(GameEvent::Chat(id, text));
                         }
-                        _ => {}
+                        None => debug!("empty or too long chat message dropped"),
                     }
+                    continue;
                 }
-            } else if msg.is_close() {
-                break;
-            }
-        } else {
+                Ok(ClientPacket::Hello(_)) => continue,
+                Err(PacketError::UnknownOpcode(_)) => continue,
+                Err(e) => {
+                    warn!(error = %e, "malformed packet");
+                    let _ = game_sender.send(GameEvent::Kick(id, "Malformed packet".to_string()));
+                    return;
+                }
+            };
+            let _ = game_sender.send(GameEvent::Input(id, input));
+        } else if msg.is_close() {
             break;
         }
     }
 
-    game_sender.send(GameEvent::Quit(id)).unwrap();
+    let _ = game_sender.send(GameEvent::Quit(id, connection));
+}
+
+/// Why a client speaking another protocol version is turned away, `None` if it speaks ours
+pub fn version_mismatch(version: u16) -> Option<&'static str> {
+    if version < PROTOCOL_VERSION {
+        Some("Client outdated, please refresh")
+    } else if version > PROTOCOL_VERSION {
+        Some("Server outdated, try again later")
+    } else {
+        None
+    }
+}
+
+/// Wait for the [`HelloPacket`] every client starts with
+pub async fn receive_hello(
+    ws_receiver: &mut SplitStream<WebSocket>,
+) -> Result<HelloPacket, &'static str> {
+    let msg = match timeout(HELLO_TIMEOUT, ws_receiver.next()).await {
+        Ok(Some(Ok(msg))) => msg,
+        Ok(_) => return Err("Connection closed"),
+        Err(_) => return Err("Handshake timed out"),
+    };
+
+    match ClientPacket::decode(msg.as_bytes()) {
+        Ok(ClientPacket::Hello(hello)) => Ok(hello),
+        // A hello this server can't read may still come from a newer or much older client
+        _ => Err(hello_version(msg.as_bytes())
+            .and_then(version_mismatch)
+            .unwrap_or("Malformed packet")),
+    }
+}
+
+/// Protocol version of a hello laid out differently than ours, which still starts with it
+fn hello_version(buf: &[u8]) -> Option<u16> {
+    let (&opcode, payload) = buf.split_first()?;
+    // Opcode of ClientPacket::Hello
+    if opcode != 0x04 {
+        return None;
+    }
+    match Message::decode(payload).ok()?.0 {
+        Message::Array(fields) => match fields.first()? {
+            Message::Uint16(version) => Some(*version),
+            Message::Uint8(version) => Some(*version as u16),
+            _ => None,
+        },
+        _ => None,
+    }
 }
@@ -0,0 +1,181 @@
+use crate::game::id::EntityId;
+use rand::random;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+struct Session {
+    token: String,
+    /// Id the connection playing it was given, a client resuming the player brings its own
+    connection: EntityId,
+    /// When the player's connection dropped, `None` while it's connected
+    disconnected: Option<Instant>,
+}
+
+fn new_token() -> String {
+    format!("{:032x}", random::<u128>())
+}
+
+/// ## Resume tokens of the players in the game
+///
+/// Owned by the game thread. A player whose connection drops is kept for a grace period,
+/// and a client sending its token in the hello gets it back. Each token is used once, the
+/// client gets a new one along with its player.
+pub struct Sessions {
+    grace: Duration,
+    tokens: HashMap<String, EntityId>,
+    sessions: HashMap<EntityId, Session>,
+}
+
+impl Sessions {
+    pub fn new(grace: Duration) -> Self {
+        Self {
+            grace,
+            tokens: HashMap::new(),
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// Start a session for a new player and get its token
+    pub fn create(&mut self, id: EntityId) -> String {
+        let token = new_token();
+        self.tokens.insert(token.clone(), id);
+        self.sessions.insert(
+            id,
+            Session {
+                token: token.clone(),
+                connection: id,
+                disconnected: None,
+            },
+        );
+        token
+    }
+
+    /// Hand the player a token belongs to over to `connection`
+    ///
+    /// Its old connection may still look alive, a dropped one is only noticed once it times
+    /// out, so it's taken over. `None` if the token is unknown, used or past its grace period.
+    pub fn resume(&mut self, token: &str, connection: EntityId) -> Option<EntityId> {
+        let id = *self.tokens.get(token)?;
+        let session = self.sessions.get_mut(&id)?;
+        if session.disconnected.is_some_and(|since| since.elapsed() >= self.grace) {
+            return None;
+        }
+
+        self.tokens.remove(token);
+        session.token = new_token();
+        self.tokens.insert(session.token.clone(), id);
+        session.connection = connection;
+        session.disconnected = None;
+        Some(id)
+    }
+
+    /// Whether another connection resumed the player since `connection` had it
+    pub fn taken_over(&self, id: EntityId, connection: EntityId) -> bool {
+        self.sessions
+            .get(&id)
+            .is_some_and(|session| session.connection != connection)
+    }
+
+    pub fn token(&self, id: EntityId) -> Option<&str> {
+        self.sessions.get(&id).map(|session| session.token.as_str())
+    }
+
+    /// Start the grace period of a player, returns `false` if it has no session
+    pub fn disconnect(&mut self, id: EntityId) -> bool {
+        match self.sessions.get_mut(&id) {
+            Some(session) => {
+                session.disconnected = Some(Instant::now());
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove(&mut self, id: EntityId) {
+        if let Some(session) = self.sessions.remove(&id) {
+            self.tokens.remove(&session.token);
+        }
+    }
+
+    /// Remove and return the players whose grace period is over
+    pub fn expired(&mut self) -> Vec<EntityId> {
+        let expired: Vec<EntityId> = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| {
+                session
+                    .disconnected
+                    .is_some_and(|since| since.elapsed() >= self.grace)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in &expired {
+            self.remove(*id);
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRACE: Duration = Duration::from_secs(30);
+
+    #[test]
+    fn resumed_players_keep_their_entity() {
+        let mut sessions = Sessions::new(GRACE);
+        let player = EntityId::from(1);
+        let token = sessions.create(player);
+
+        assert!(sessions.disconnect(player));
+        assert_eq!(sessions.resume(&token, EntityId::from(2)), Some(player));
+        assert!(sessions.expired().is_empty());
+        // A new token comes with the player
+        assert_ne!(sessions.token(player), Some(token.as_str()));
+    }
+
+    #[test]
+    fn unknown_and_used_tokens_are_refused() {
+        let mut sessions = Sessions::new(GRACE);
+        let player = EntityId::from(1);
+        let token = sessions.create(player);
+
+        assert_eq!(sessions.resume("not a token", EntityId::from(2)), None);
+        assert_eq!(sessions.resume(&token, EntityId::from(2)), Some(player));
+        assert_eq!(sessions.resume(&token, EntityId::from(3)), None);
+
+        let token = sessions.token(player).unwrap().to_string();
+        sessions.remove(player);
+        assert_eq!(sessions.resume(&token, EntityId::from(4)), None);
+    }
+
+    #[test]
+    fn tokens_are_refused_past_the_grace_period() {
+        let mut sessions = Sessions::new(Duration::ZERO);
+        let player = EntityId::from(1);
+        let token = sessions.create(player);
+
+        sessions.disconnect(player);
+        // Before the game looked for expired players
+        assert_eq!(sessions.resume(&token, EntityId::from(2)), None);
+        assert_eq!(sessions.expired(), [player]);
+        assert_eq!(sessions.resume(&token, EntityId::from(2)), None);
+    }
+
+    #[test]
+    fn connected_players_can_be_taken_over() {
+        let mut sessions = Sessions::new(GRACE);
+        let player = EntityId::from(1);
+        let token = sessions.create(player);
+
+        // The old connection dropped without closing, the game didn't notice yet
+        let connection = EntityId::from(2);
+        assert_eq!(sessions.resume(&token, connection), Some(player));
+        assert!(sessions.taken_over(player, player));
+        assert!(!sessions.taken_over(player, connection));
+    }
+}
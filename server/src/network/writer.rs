@@ -0,0 +1,171 @@
+use crate::network::metrics::sent;
+use bytes::Bytes;
+use futures_util::{stream::SplitSink, SinkExt};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{
+        mpsc::{channel, error::TrySendError, Receiver, Sender},
+        watch,
+    },
+    task::JoinHandle,
+    time::sleep,
+};
+use tokio_util::sync::CancellationToken;
+use warp::filters::ws::{Message as SocketMessage, WebSocket};
+
+/// Close code for clients whose game is over, like at the end of a replay
+pub const NORMAL_CLOSURE: u16 = 1000;
+
+/// Close code for clients that are turned away or kicked
+pub const POLICY_VIOLATION: u16 = 1008;
+
+/// Close code for clients disconnected because the server is stopping
+pub const GOING_AWAY: u16 = 1001;
+
+/// How long a closed connection has to take what was queued before it, the close frame
+/// included, before its socket is dropped anyway
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How many packets can wait for a connection, snapshots aside, before it's closed for not
+/// keeping up
+const QUEUE_SIZE: usize = 64;
+
+/// ## Where the frames of one connection wait for its writer task
+///
+/// Packets are queued in order, and a client that lets [`QUEUE_SIZE`] of them pile up is
+/// disconnected rather than having any dropped. Snapshots get a slot of their own that only
+/// keeps the newest one, so a client that falls behind skips the stale ones. Frames are encoded
+/// once, so sending the same one to many connections only clones the `Bytes`.
+#[derive(Clone)]
+pub struct Outbox {
+    packets: Sender<Bytes>,
+    snapshot: Arc<watch::Sender<Option<Bytes>>>,
+    /// Code and reason of the close frame, sent once the packets queued before it are
+    close: Arc<Mutex<Option<(u16, String)>>>,
+    closed: CancellationToken,
+}
+
+impl Outbox {
+    /// Queue a frame that must reach the client, like the welcome packet
+    pub fn packet(&self, data: Bytes) {
+        if self.closed.is_cancelled() {
+            return;
+        }
+        match self.packets.try_send(data) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.close(POLICY_VIOLATION, "Connection too slow".to_string());
+            }
+            // The writer is gone already, the socket closed
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+
+    /// Replace the snapshot waiting to be sent, if the last one wasn't yet
+    pub fn snapshot(&self, data: Bytes) {
+        self.snapshot.send_replace(Some(data));
+    }
+
+    /// Close the connection once what was queued before is sent, nothing is queued after it
+    ///
+    /// Only the first close counts. Whoever reads the socket is told through
+    /// [`Outbox::closed`], and stops.
+    pub fn close(&self, code: u16, reason: String) {
+        self.close.lock().unwrap().get_or_insert((code, reason));
+        self.closed.cancel();
+    }
+
+    /// Resolves once the connection was closed from the server's side
+    pub async fn closed(&self) {
+        self.closed.cancelled().await
+    }
+
+    /// Packets waiting to be written
+    pub fn queued(&self) -> usize {
+        self.packets.max_capacity() - self.packets.capacity()
+    }
+}
+
+/// Start writing to a socket from its own task
+///
+/// The task ends once the connection is closed or every [`Outbox`] is dropped.
+pub fn spawn_writer(sink: SplitSink<WebSocket, SocketMessage>) -> (Outbox, JoinHandle<()>) {
+    let (packets, packet_receiver) = channel(QUEUE_SIZE);
+    let (snapshot, snapshot_receiver) = watch::channel(None);
+    let outbox = Outbox {
+        packets,
+        snapshot: Arc::new(snapshot),
+        close: Arc::new(Mutex::new(None)),
+        closed: CancellationToken::new(),
+    };
+
+    // Not the senders, the task must not keep its own queues open
+    let writer = tokio::spawn(write(
+        sink,
+        packet_receiver,
+        snapshot_receiver,
+        outbox.close.clone(),
+        outbox.closed.clone(),
+    ));
+    (outbox, writer)
+}
+
+/// Write the frames of one connection until it closes or its [`Outbox`] is dropped
+async fn write(
+    mut sink: SplitSink<WebSocket, SocketMessage>,
+    mut packets: Receiver<Bytes>,
+    mut snapshots: watch::Receiver<Option<Bytes>>,
+    close: Arc<Mutex<Option<(u16, String)>>>,
+    closed: CancellationToken,
+) {
+    let writing = async {
+        loop {
+            tokio::select! {
+                // Packets first, so the ones queued before a close are still sent
+                biased;
+                data = packets.recv() => {
+                    let Some(data) = data else { break };
+                    if !send(&mut sink, data).await {
+                        return;
+                    }
+                }
+                _ = closed.cancelled() => break,
+                changed = snapshots.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                    let data = snapshots.borrow_and_update().clone();
+                    if let Some(data) = data {
+                        if !send(&mut sink, data).await {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+
+        let close = close.lock().unwrap().take();
+        if let Some((code, reason)) = close {
+            let _ = sink.send(SocketMessage::close_with(code, reason)).await;
+        }
+        let _ = sink.close().await;
+    };
+
+    // A client that stopped reading would keep a closed connection open forever otherwise
+    tokio::select! {
+        _ = writing => {}
+        _ = async {
+            closed.cancelled().await;
+            sleep(CLOSE_TIMEOUT).await;
+        } => {}
+    }
+}
+
+/// Write one frame, returns `false` once the socket is gone
+async fn send(sink: &mut SplitSink<WebSocket, SocketMessage>, data: Bytes) -> bool {
+    sent(data.len());
+    sink.send(SocketMessage::binary(data)).await.is_ok()
+}